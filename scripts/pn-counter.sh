@@ -0,0 +1,22 @@
+#!/usr/bin/sh
+
+usage() {
+    echo "usage: $0 <maelstrom-binary-path>"
+}
+
+if [ -z $1 ]; then
+    echo "no maelstrom binary path provided"
+    usage
+    return 1
+elif ! test -f $1; then
+    echo "maelstrom binary not found"
+    usage
+    return 1
+fi
+
+if cargo build --release ; then
+    $1 test -w pn-counter --bin ./target/release/pn-counter --node-count 3 --rate 100 --time-limit 20 --nemesis partition
+else
+    echo "cargo build error"
+    return 1
+fi
@@ -0,0 +1,356 @@
+use crate::{
+    log,
+    log::Level,
+    protocol::{Message, Payload},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+mod sha256;
+
+/// The environment variable holding the key shared by the nodes to sign their messages.
+pub const AUTH_KEY_ENV: &str = "VORTEX_AUTH_KEY";
+
+/// The field of a message body holding its MAC.
+pub const MAC_FIELD: &str = "mac";
+
+/// The field of a message body holding its nonce, which is covered by the MAC.
+pub const NONCE_FIELD: &str = "nonce";
+
+/// The number of nonces before the highest one seen from a peer that are still accepted,
+/// so that messages that are reordered in flight are not mistaken for replays.
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// The body fields that are not covered by the MAC.
+/// The trace tag is added by the tracer after a message is signed.
+const UNSIGNED_FIELDS: &[&str] = &[MAC_FIELD, "trace"];
+
+#[derive(thiserror::Error, Debug)]
+pub enum AuthError {
+    #[error("message from {0} has no mac")]
+    Unsigned(String),
+    #[error("message from {0} has an invalid mac")]
+    Tampered(String),
+    #[error("message from {0} has no nonce")]
+    MissingNonce(String),
+    #[error("message from {src} replays nonce {nonce}")]
+    Replayed { src: String, nonce: u64 },
+    #[error("message could not be decoded: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// This tracks the nonces seen from a peer, which are accepted at most once
+/// and only if they are within `REPLAY_WINDOW` of the highest one.
+#[derive(Default)]
+struct ReplayWindow {
+    highest: u64,
+    /// The nonces seen at or below the highest one, where bit `i` is the nonce `highest - i`.
+    seen: u64,
+}
+
+impl ReplayWindow {
+    /// This records the nonce, returning false if it was seen before or is too old to tell.
+    fn accept(&mut self, nonce: u64) -> bool {
+        if nonce > self.highest {
+            let shift = nonce - self.highest;
+            self.seen = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = nonce;
+            return true;
+        }
+        let age = self.highest - nonce;
+        if age >= REPLAY_WINDOW || self.seen & (1 << age) != 0 {
+            return false;
+        }
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+/// This is middleware that signs messages to peers with an HMAC-SHA256 over their source,
+/// destination and body, and verifies the messages from peers, so that a node can ignore messages
+/// that were forged, tampered with or replayed by an adversarial node.
+/// Each signed message carries a nonce that increases with every message,
+/// starting from the time the node started so that a restarted node does not reuse nonces.
+/// Messages to and from clients and services are neither signed nor verified since they do not know the key,
+/// and neither are error, init and topology messages, as they are parsed without their extra fields.
+/// This is for experiments with adversarial nodes in the simulator and is not hardened against anything beyond that.
+pub struct Authenticator {
+    /// The shared key, which is empty if signing is disabled.
+    key: Vec<u8>,
+    /// The nodes in the cluster, whose messages are signed and verified.
+    peers: HashSet<String>,
+    /// The nonce of the next signed message.
+    nonce: u64,
+    /// The nonces seen from each peer, or `None` if replays are not rejected.
+    windows: Option<HashMap<String, ReplayWindow>>,
+}
+
+impl Authenticator {
+    /// This creates an authenticator for messages between the peers with the shared key.
+    /// Replays are rejected unless disabled with `allow_replays`.
+    pub fn new(key: &[u8], peers: &[String]) -> Self {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        Self {
+            key: key.to_vec(),
+            peers: peers.iter().cloned().collect(),
+            nonce: started,
+            windows: Some(HashMap::new()),
+        }
+    }
+
+    /// This makes the authenticator accept replayed messages as long as their MAC is valid,
+    /// for workloads whose requests are idempotent or that want to observe replays themselves.
+    pub fn allow_replays(mut self) -> Self {
+        self.windows = None;
+        self
+    }
+
+    /// This makes the nonces of signed messages start from `nonce` instead of the current time,
+    /// so that the same messages are signed identically on every run.
+    pub fn starting_at(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+
+    /// This creates an authenticator that neither signs nor verifies messages.
+    pub fn disabled() -> Self {
+        Self {
+            key: Vec::new(),
+            peers: HashSet::new(),
+            nonce: 0,
+            windows: None,
+        }
+    }
+
+    /// This creates an authenticator with the key in `VORTEX_AUTH_KEY` if it is set,
+    /// and a disabled one otherwise.
+    pub fn from_env(peers: &[String]) -> Self {
+        match env::var(AUTH_KEY_ENV) {
+            Ok(key) if !key.is_empty() => Self::new(key.as_bytes(), peers),
+            _ => Self::disabled(),
+        }
+    }
+
+    /// Whether messages are signed and verified at all.
+    pub fn is_enabled(&self) -> bool {
+        !self.key.is_empty()
+    }
+
+    /// The MAC of the message as a hex string, ignoring any fields that are not signed.
+    fn mac(&self, message: &Value) -> Result<String, serde_json::Error> {
+        let mut message = message.clone();
+        if let Some(body) = message["body"].as_object_mut() {
+            for field in UNSIGNED_FIELDS {
+                body.remove(*field);
+            }
+        }
+        // The keys of a `Value` are sorted, so that both ends encode the same bytes.
+        let bytes = serde_json::to_vec(&message)?;
+        Ok(sha256::hmac(&self.key, &bytes)
+            .iter()
+            .fold(String::new(), |mut hex, b| {
+                let _ = write!(hex, "{:02x}", b);
+                hex
+            }))
+    }
+
+    /// Whether the message is one that is signed, which is a custom message between the node and a peer.
+    fn signed<T>(&self, message: &Message<T>, peer: &str) -> bool {
+        self.is_enabled()
+            && self.peers.contains(peer)
+            && matches!(message.body.payload, Payload::Custom(_))
+    }
+
+    /// This converts the message to JSON, adding a nonce and a MAC to its body if it is a custom message to a peer.
+    pub fn sign<T>(&mut self, message: &Message<T>) -> Result<Message<Value>, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        let mut value = serde_json::to_value(message)?;
+        if self.signed(message, &message.dest) {
+            if let Some(body) = value["body"].as_object_mut() {
+                self.nonce += 1;
+                body.insert(NONCE_FIELD.to_string(), self.nonce.into());
+            }
+            let mac = self.mac(&value)?;
+            if let Some(body) = value["body"].as_object_mut() {
+                body.insert(MAC_FIELD.to_string(), mac.into());
+            }
+        }
+        serde_json::from_value(value)
+    }
+
+    /// This checks the MAC and nonce of a custom message from a peer,
+    /// and converts the message to its payload type without them.
+    pub fn verify<T>(&mut self, message: Message<Value>) -> Result<Message<T>, AuthError>
+    where
+        T: DeserializeOwned,
+    {
+        let mut value = serde_json::to_value(&message)?;
+        if self.signed(&message, &message.src) {
+            let mac = value["body"]
+                .as_object_mut()
+                .and_then(|body| body.remove(MAC_FIELD))
+                .ok_or_else(|| AuthError::Unsigned(message.src.clone()))?;
+            let expected = self.mac(&value)?;
+            let matches = mac.as_str().is_some_and(|mac| {
+                mac.len() == expected.len()
+                    && mac
+                        .bytes()
+                        .zip(expected.bytes())
+                        .fold(0, |diff, (a, b)| diff | (a ^ b))
+                        == 0
+            });
+            if !matches {
+                return Err(AuthError::Tampered(message.src));
+            }
+            let nonce = value["body"]
+                .as_object_mut()
+                .and_then(|body| body.remove(NONCE_FIELD))
+                .and_then(|nonce| nonce.as_u64())
+                .ok_or_else(|| AuthError::MissingNonce(message.src.clone()))?;
+            if let Some(windows) = &mut self.windows {
+                if !windows
+                    .entry(message.src.clone())
+                    .or_default()
+                    .accept(nonce)
+                {
+                    return Err(AuthError::Replayed {
+                        src: message.src,
+                        nonce,
+                    });
+                }
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// This verifies a message like `verify`, but logs and drops messages that fail verification
+    /// instead of returning an error, which is what a node usually wants to do with them.
+    pub fn accept<T>(
+        &mut self,
+        message: Message<Value>,
+    ) -> Result<Option<Message<T>>, serde_json::Error>
+    where
+        T: DeserializeOwned,
+    {
+        match self.verify(message) {
+            Ok(message) => Ok(Some(message)),
+            Err(AuthError::Json(e)) => Err(e),
+            Err(e) => {
+                log!(Level::Warn, "dropped message: {}", e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+    use serde_json::json;
+
+    fn peers() -> Vec<String> {
+        vec!["n1".to_string(), "n2".to_string()]
+    }
+
+    fn gossip() -> Message<Value> {
+        Message::new("n1", "n2", json!({"type": "gossip", "messages": [1, 2]}))
+    }
+
+    #[test]
+    fn signed_messages_verify() {
+        let mut n1 = Authenticator::new(b"key", &peers());
+        let mut n2 = Authenticator::new(b"key", &peers());
+        let signed = n1.sign(&gossip()).unwrap();
+        let message: Message<Value> = n2.verify(signed).unwrap();
+        assert_eq!(
+            serde_json::to_value(message).unwrap(),
+            serde_json::to_value(gossip()).unwrap()
+        );
+    }
+
+    #[test]
+    fn tampered_messages_are_rejected() {
+        let mut n1 = Authenticator::new(b"key", &peers());
+        let mut n2 = Authenticator::new(b"key", &peers());
+        let mut signed = n1.sign(&gossip()).unwrap();
+        if let Payload::Custom(body) = &mut signed.body.payload {
+            body["messages"] = json!([1, 3]);
+        }
+        assert!(matches!(
+            n2.verify::<Value>(signed),
+            Err(AuthError::Tampered(_))
+        ));
+        let forged = Authenticator::new(b"other", &peers())
+            .sign(&gossip())
+            .unwrap();
+        assert!(matches!(
+            n2.verify::<Value>(forged),
+            Err(AuthError::Tampered(_))
+        ));
+        assert!(matches!(
+            n2.verify::<Value>(gossip()),
+            Err(AuthError::Unsigned(_))
+        ));
+    }
+
+    #[test]
+    fn replays_are_rejected_unless_allowed() {
+        let mut n1 = Authenticator::new(b"key", &peers());
+        let signed = n1.sign(&gossip()).unwrap();
+        let mut n2 = Authenticator::new(b"key", &peers());
+        assert!(n2.verify::<Value>(signed.clone()).is_ok());
+        assert!(matches!(
+            n2.verify::<Value>(signed.clone()),
+            Err(AuthError::Replayed { .. })
+        ));
+        let mut n2 = Authenticator::new(b"key", &peers()).allow_replays();
+        assert!(n2.verify::<Value>(signed.clone()).is_ok());
+        assert!(n2.verify::<Value>(signed).is_ok());
+    }
+
+    #[test]
+    fn error_replies_between_peers_pass_unsigned() {
+        let mut n1 = Authenticator::new(b"key", &peers());
+        let mut n2 = Authenticator::new(b"key", &peers());
+        let mut request = gossip();
+        request.body.msg_id = Some(1);
+        let reply = request.error_reply(ErrorCode::NotSupported, "no");
+        let sent = n2.sign(&reply).unwrap();
+        let received: Message<Value> = n1.verify(sent).unwrap();
+        assert!(matches!(
+            received.body.payload,
+            Payload::Error {
+                code: ErrorCode::NotSupported,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn messages_to_clients_are_left_unsigned() {
+        let mut n1 = Authenticator::new(b"key", &peers());
+        let message = Message::new("n1", "c1", json!({"type": "read_ok", "messages": []}));
+        let sent = n1.sign(&message).unwrap();
+        assert_eq!(
+            serde_json::to_value(sent).unwrap(),
+            serde_json::to_value(message).unwrap()
+        );
+    }
+}
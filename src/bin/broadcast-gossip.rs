@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::Infallible,
+    time::{Duration, Instant},
+};
+use vortex::{
+    prelude::*,
+    runtime::{clock, TimerId},
+    topology::Topology,
+};
+
+/// The time between gossip rounds while new messages are spreading, which trades latency for fewer messages.
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The longest time between gossip rounds once they stop yielding new messages.
+const MAX_GOSSIP_INTERVAL: Duration = Duration::from_millis(1600);
+
+/// The number of children of each node in the tree that messages are gossiped along.
+const FANOUT: usize = 4;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Data {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        messages: Vec<usize>,
+    },
+    Gossip {
+        messages: Vec<usize>,
+    },
+    GossipOk,
+    /// The timer payload that starts a gossip round.
+    Tick,
+}
+
+/// The gossip rounds with one neighbor.
+#[derive(Serialize)]
+struct PeerRounds {
+    /// The time between rounds with the neighbor.
+    interval: Duration,
+    /// The number of new messages from the neighbor since the last round with it.
+    yields: usize,
+    /// When the next round with the neighbor is due.
+    #[serde(skip)]
+    due: Instant,
+}
+
+/// This estimates whether the messages have converged with each neighbor
+/// from how many new messages each round with it yields,
+/// so that rounds with a neighbor slow down once neither side has anything left to give the other,
+/// and rounds with every neighbor speed up again as soon as any new message arrives.
+#[derive(Serialize)]
+struct Rounds {
+    /// The rounds with each neighbor, in the order of their IDs.
+    peers: BTreeMap<String, PeerRounds>,
+    /// The timer of the next round.
+    #[serde(skip)]
+    next: Option<TimerId>,
+}
+
+impl Rounds {
+    /// This starts the rounds with every neighbor, each due one interval from `now`.
+    fn new(neighbors: &[String], now: Instant) -> Self {
+        let peers = neighbors
+            .iter()
+            .map(|neighbor| {
+                let rounds = PeerRounds {
+                    interval: GOSSIP_INTERVAL,
+                    yields: 0,
+                    due: now + GOSSIP_INTERVAL,
+                };
+                (neighbor.clone(), rounds)
+            })
+            .collect();
+        Self { peers, next: None }
+    }
+
+    /// This records `new` messages from `src`, which churn means have to be spread again,
+    /// returning whether the rounds with any neighbor sped up.
+    fn observe(&mut self, src: &str, new: usize, now: Instant) -> bool {
+        if new == 0 {
+            return false;
+        }
+        if let Some(peer) = self.peers.get_mut(src) {
+            peer.yields += new;
+        }
+        let mut sped_up = false;
+        for peer in self.peers.values_mut() {
+            if peer.interval > GOSSIP_INTERVAL {
+                peer.interval = GOSSIP_INTERVAL;
+                peer.due = peer.due.min(now + GOSSIP_INTERVAL);
+                sped_up = true;
+            }
+        }
+        sped_up
+    }
+
+    /// The neighbors whose rounds are due by `now`.
+    fn due(&self, now: Instant) -> Vec<String> {
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.due <= now)
+            .map(|(neighbor, _)| neighbor.clone())
+            .collect()
+    }
+
+    /// This ends a round with the neighbor, doubling the time until the next one
+    /// if the round sent it nothing and it yielded nothing since the last one.
+    fn end(&mut self, neighbor: &str, sent: bool, now: Instant) {
+        let Some(peer) = self.peers.get_mut(neighbor) else {
+            return;
+        };
+        peer.interval = if !sent && peer.yields == 0 {
+            (peer.interval * 2).min(MAX_GOSSIP_INTERVAL)
+        } else {
+            GOSSIP_INTERVAL
+        };
+        peer.yields = 0;
+        peer.due = now + peer.interval;
+    }
+
+    /// When the next round with any neighbor is due.
+    fn next_due(&self) -> Option<Instant> {
+        self.peers.values().map(|peer| peer.due).min()
+    }
+}
+
+/// This represents a broadcast node that batches the messages it knows
+/// and gossips them along a tree of the cluster in rounds,
+/// sending each neighbor only the messages it has not acknowledged.
+/// The rounds with a neighbor slow down while they yield no new messages,
+/// cutting the messages sent once the cluster converged.
+#[derive(Serialize)]
+struct GossipNode {
+    messages: BTreeSet<usize>,
+    /// The nodes adjacent to this one in the gossip tree.
+    neighbors: Vec<String>,
+    /// The messages that each neighbor is known to have.
+    known: HashMap<String, BTreeSet<usize>>,
+    rounds: Rounds,
+    #[serde(skip)]
+    timers: Timers<Data>,
+}
+
+impl GossipNode {
+    fn new() -> Self {
+        Self {
+            messages: BTreeSet::new(),
+            neighbors: Vec::new(),
+            known: HashMap::new(),
+            rounds: Rounds::new(&[], clock::now()),
+            timers: Timers::new(),
+        }
+    }
+
+    /// This sends the neighbor the messages it is not known to have, returning whether there were any.
+    fn gossip(&self, neighbor: &str, ctx: &mut Context<Data>) -> bool {
+        let messages: Vec<usize> = match self.known.get(neighbor) {
+            Some(known) => self.messages.difference(known).copied().collect(),
+            None => self.messages.iter().copied().collect(),
+        };
+        if messages.is_empty() {
+            return false;
+        }
+        ctx.send(neighbor, Data::Gossip { messages });
+        true
+    }
+
+    /// This schedules the next round for when it is due, replacing any round scheduled before.
+    fn schedule(&mut self, now: Instant) {
+        if let Some(next) = self.rounds.next.take() {
+            self.timers.cancel(next);
+        }
+        if let Some(due) = self.rounds.next_due() {
+            let delay = due.saturating_duration_since(now);
+            self.rounds.next = Some(self.timers.after(delay, Data::Tick));
+        }
+    }
+
+    /// This adds the messages from `src`, bringing the next round forward if new ones arrived after rounds had slowed.
+    fn learn(&mut self, src: &str, messages: &[usize], now: Instant) {
+        let before = self.messages.len();
+        self.messages.extend(messages);
+        if self.rounds.observe(src, self.messages.len() - before, now) {
+            self.schedule(now);
+        }
+    }
+}
+
+impl StateMachine<Data> for GossipNode {
+    type Error = Infallible;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Data>>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        for request in messages {
+            match &request.body.payload {
+                Payload::Custom(Data::Broadcast { message }) => {
+                    self.learn(&request.src, &[*message], ctx.now());
+                    ctx.reply(&request, Data::BroadcastOk);
+                }
+                Payload::Custom(Data::Read) => {
+                    ctx.reply(
+                        &request,
+                        Data::ReadOk {
+                            messages: self.messages.iter().copied().collect(),
+                        },
+                    );
+                }
+                Payload::Custom(Data::Gossip { messages }) => {
+                    self.learn(&request.src, messages, ctx.now());
+                    self.known
+                        .entry(request.src.clone())
+                        .or_default()
+                        .extend(messages);
+                    ctx.reply(&request, Data::GossipOk);
+                }
+                Payload::Custom(Data::Tick) if request.src == ctx.node_id() => {
+                    let now = ctx.now();
+                    for neighbor in self.rounds.due(now) {
+                        let sent = self.gossip(&neighbor, ctx);
+                        self.rounds.end(&neighbor, sent, now);
+                    }
+                    self.rounds.next = None;
+                    self.schedule(now);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn on_init(&mut self, node_id: &str, node_ids: &[String]) -> Result<(), Self::Error> {
+        self.neighbors = Topology::tree(node_ids, FANOUT).neighbors(node_id).to_vec();
+        let now = clock::now();
+        self.rounds = Rounds::new(&self.neighbors, now);
+        self.schedule(now);
+        Ok(())
+    }
+
+    fn expects_reply(&self, message: &Message<Data>) -> bool {
+        matches!(message.body.payload, Payload::Custom(Data::Gossip { .. }))
+    }
+
+    fn on_reply(
+        &mut self,
+        request: Message<Data>,
+        reply: Message<Data>,
+        _ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        if let (Payload::Custom(Data::Gossip { messages }), Payload::Custom(Data::GossipOk)) =
+            (request.body.payload, reply.body.payload)
+        {
+            self.known.entry(reply.src).or_default().extend(messages);
+        }
+        Ok(())
+    }
+
+    fn timers(&mut self) -> Option<&mut Timers<Data>> {
+        Some(&mut self.timers)
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `broadcast 5` or `read` into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    match command {
+        ["broadcast", message] => message
+            .parse()
+            .ok()
+            .map(|message| Data::Broadcast { message }),
+        ["read"] => Some(Data::Read),
+        _ => None,
+    }
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(|_| GossipNode::new()).with_repl(expand).run()
+}
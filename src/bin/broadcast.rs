@@ -1,143 +1,185 @@
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
-    error,
-    io::{self, BufRead},
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::Infallible,
 };
-use vortex::{Message, MessageError, Node, Payload, StateMachine};
+use vortex::prelude::*;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// The number of times a broadcast is retransmitted to a neighbor that has not acknowledged it.
+const BROADCAST_RETRIES: u32 = 20;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Workload)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Data {
     Broadcast {
-        msg_id: usize,
         message: usize,
     },
-    BroadcastOk {
-        msg_id: usize,
-        in_reply_to: usize,
-    },
-    Read {
-        msg_id: usize,
-    },
+    BroadcastOk,
+    Read,
     ReadOk {
-        msg_id: usize,
-        in_reply_to: usize,
         messages: Vec<usize>,
     },
-    Topology {
-        msg_id: usize,
-        topology: HashMap<String, Vec<String>>,
-    },
-    TopologyOk {
-        msg_id: usize,
-        in_reply_to: usize,
+    /// The messages a neighbor received within a batch window.
+    Gossip {
+        messages: Vec<usize>,
     },
+    GossipOk,
+    /// The timer payload that flushes the batches to the neighbors.
+    Tick,
 }
 
+/// This represents a broadcast node that forwards the messages it has not seen to its neighbors,
+/// coalescing the messages received within a batch window into a single gossip per neighbor.
+#[derive(Serialize)]
 struct BroadcastNode {
-    id: String,
-    msg_id_counter: usize,
-    messages: HashSet<usize>,
+    /// The messages seen, which are kept sorted so that reads list them in the same order on every run.
+    messages: BTreeSet<usize>,
     neighbors: Vec<String>,
+    /// The messages that each neighbor is known to have, because it acknowledged or gossiped them,
+    /// which are not sent to it again.
+    acknowledged: HashMap<String, HashSet<usize>>,
+    #[serde(skip)]
+    batcher: Batcher<usize>,
+    #[serde(skip)]
+    timers: Timers<Data>,
 }
 
 impl BroadcastNode {
-    fn new(id: &str) -> Self {
+    fn new() -> Self {
         Self {
-            id: id.to_string(),
-            msg_id_counter: 0,
-            messages: HashSet::new(),
+            messages: BTreeSet::new(),
             neighbors: Vec::new(),
+            acknowledged: HashMap::new(),
+            batcher: Batcher::from_env(),
+            timers: Timers::new(),
+        }
+    }
+
+    /// This records the messages, adding the ones not seen before to the batches of the neighbors
+    /// other than the one they came from, which is known to have them.
+    fn receive(&mut self, messages: &[usize], src: &str) {
+        if self.neighbors.iter().any(|n| n == src) {
+            self.acknowledged
+                .entry(src.to_string())
+                .or_default()
+                .extend(messages);
+        }
+        for &message in messages {
+            if !self.messages.insert(message) {
+                continue;
+            }
+            for n in self.neighbors.iter().filter(|n| *n != src) {
+                self.batcher.add(n, message);
+            }
+        }
+    }
+
+    /// This sends each neighbor its batch of messages, leaving out the ones it is known to have by now.
+    fn flush(&mut self, ctx: &mut Context<Data>) {
+        for (neighbor, mut messages) in self.batcher.flush() {
+            if let Some(acknowledged) = self.acknowledged.get(&neighbor) {
+                messages.retain(|message| !acknowledged.contains(message));
+            }
+            if !messages.is_empty() {
+                ctx.send(&neighbor, Data::Gossip { messages });
+            }
         }
     }
 }
 
 impl StateMachine<Data> for BroadcastNode {
+    type Error = Infallible;
+
     fn apply(
         &mut self,
         messages: Vec<Message<Data>>,
-    ) -> Result<Vec<Message<Data>>, Box<dyn error::Error>> {
-        let mut responses = Vec::new();
-        for message in messages {
-            let Message { src, dest, body } = message;
-            match body {
-                Payload::Custom(Data::Broadcast { msg_id, message }) => {
-                    if !self.messages.contains(&message) {
-                        self.neighbors
-                            .iter()
-                            .filter(|&n| *n != src && *n != dest)
-                            .map(|n| {
-                                self.msg_id_counter += 1;
-                                let src = self.id.to_string();
-                                let dest = n.to_string();
-                                let msg_id = self.msg_id_counter;
-                                let body = Payload::Custom(Data::Broadcast { msg_id, message });
-                                Message { src, dest, body }
-                            })
-                            .for_each(|m| responses.push(m));
-                    }
-                    self.msg_id_counter += 1;
-                    self.messages.insert(message);
-                    responses.push(Message {
-                        src: dest,
-                        dest: src,
-                        body: Payload::Custom(Data::BroadcastOk {
-                            msg_id: self.msg_id_counter,
-                            in_reply_to: msg_id,
-                        }),
-                    });
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        for request in messages {
+            match request.body.payload {
+                Payload::Custom(Data::Broadcast { message }) => {
+                    self.receive(&[message], &request.src);
+                    ctx.reply(&request, Data::broadcast_ok());
                 }
-                Payload::Custom(Data::Read { msg_id }) => {
-                    self.msg_id_counter += 1;
-                    responses.push(Message {
-                        src: dest,
-                        dest: src,
-                        body: Payload::Custom(Data::ReadOk {
-                            msg_id: self.msg_id_counter,
-                            in_reply_to: msg_id,
-                            messages: self.messages.iter().copied().collect(),
-                        }),
-                    });
+                Payload::Custom(Data::Gossip { ref messages }) => {
+                    self.receive(messages, &request.src);
+                    ctx.reply(&request, Data::gossip_ok());
                 }
-                Payload::Custom(Data::Topology { msg_id, topology }) => {
-                    self.msg_id_counter += 1;
-                    self.neighbors = topology.get(&self.id).unwrap_or(&vec![]).clone();
-                    responses.push(Message {
-                        src: dest,
-                        dest: src,
-                        body: Payload::Custom(Data::TopologyOk {
-                            msg_id: self.msg_id_counter,
-                            in_reply_to: msg_id,
-                        }),
-                    });
+                Payload::Custom(Data::Tick) if request.src == ctx.node_id() => self.flush(ctx),
+                Payload::Custom(Data::Read) => {
+                    ctx.reply(
+                        &request,
+                        Data::read_ok(self.messages.iter().copied().collect()),
+                    );
                 }
                 _ => {}
             }
         }
-        Ok(responses)
+        if self.batcher.is_immediate() {
+            self.flush(ctx);
+        }
+        Ok(())
     }
-}
 
-fn main() -> Result<(), Box<dyn error::Error>> {
-    let mut stdin = io::stdin().lock();
-    let mut stdout = io::stdout().lock();
-
-    let init: Message<Data> = Message::from_reader(&mut stdin)?;
-    let id = match &init.body {
-        Payload::Init { node_id, .. } => Ok(node_id.to_string()),
-        _ => Err(MessageError::Invalid),
-    }?;
-    let (mut node, resp) = Node::init(init, Box::new(BroadcastNode::new(&id)))?;
-    resp.write(&mut stdout)?;
-
-    for line in stdin.lines() {
-        let message: Message<Data> = Message::from_str(&line?)?;
-        let responses = node.recv_messages(vec![message])?;
-        for res in responses {
-            res.write(&mut stdout)?;
+    fn on_init(&mut self, _node_id: &str, _node_ids: &[String]) -> Result<(), Self::Error> {
+        if !self.batcher.is_immediate() {
+            self.timers.every(self.batcher.window(), Data::Tick);
+        }
+        Ok(())
+    }
+
+    fn on_topology(&mut self, neighbors: &[String]) -> Result<(), Self::Error> {
+        self.neighbors = neighbors.to_vec();
+        Ok(())
+    }
+
+    fn expects_reply(&self, message: &Message<Data>) -> bool {
+        message.is_request()
+    }
+
+    fn on_reply(
+        &mut self,
+        request: Message<Data>,
+        reply: Message<Data>,
+        _ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        if let (Payload::Custom(Data::Gossip { messages }), Payload::Custom(Data::GossipOk)) =
+            (request.body.payload, reply.body.payload)
+        {
+            self.acknowledged
+                .entry(reply.src)
+                .or_default()
+                .extend(messages);
         }
+        Ok(())
     }
-    Ok(())
+
+    fn timers(&mut self) -> Option<&mut Timers<Data>> {
+        Some(&mut self.timers)
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `broadcast 5` or `read` into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    match command {
+        ["broadcast", message] => message
+            .parse()
+            .ok()
+            .map(|message| Data::Broadcast { message }),
+        ["read"] => Some(Data::Read),
+        _ => None,
+    }
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(|_| BroadcastNode::new())
+        .with_repl(expand)
+        .with_retries(RetryPolicy::new(BROADCAST_RETRIES))
+        .authenticated()
+        .run()
 }
@@ -0,0 +1,465 @@
+use std::{
+    env, error, fs,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{self, Command, Stdio},
+};
+use vortex::results::Results;
+
+/// What Maelstrom prints when a test passes.
+const PASSED: &str = "Everything looks good!";
+
+/// What Maelstrom prints when a test fails.
+const FAILED: &str = "Analysis invalid!";
+
+/// The directory Maelstrom writes its results to, relative to where it is run.
+const STORE: &str = "store";
+
+/// The manifest that `cargo vortex new` registers the new binary in.
+const MANIFEST: &str = "Cargo.toml";
+
+/// The source of a new workload binary, where `__NODE__` is replaced with the name of its state machine.
+const BIN_TEMPLATE: &str = r#"use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use vortex::prelude::*;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Data {
+    // TODO: replace these with the workload's messages.
+    Echo { echo: String },
+    EchoOk { echo: String },
+}
+
+#[derive(Serialize)]
+struct __NODE__ {
+    id: String,
+}
+
+impl StateMachine<Data> for __NODE__ {
+    type Error = Infallible;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Data>>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        for message in messages {
+            // TODO: handle the workload's messages.
+            if let Payload::Custom(Data::Echo { echo }) = &message.body.payload {
+                ctx.reply(&message, Data::EchoOk { echo: echo.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    match command {
+        // TODO: expand the workload's commands.
+        ["echo", echo @ ..] => Some(Data::Echo {
+            echo: echo.join(" "),
+        }),
+        _ => None,
+    }
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(|id| __NODE__ { id: id.to_string() })
+        .with_repl(expand)
+        .run()
+}
+"#;
+
+/// The script running a new workload binary under Maelstrom, where `__NAME__` is replaced with its name.
+const SCRIPT_TEMPLATE: &str = r#"#!/usr/bin/sh
+
+usage() {
+    echo "usage: $0 <maelstrom-binary-path>"
+}
+
+if [ -z $1 ]; then
+    echo "no maelstrom binary path provided"
+    usage
+    return 1
+elif ! test -f $1; then
+    echo "maelstrom binary not found"
+    usage
+    return 1
+fi
+
+# TODO: set the Maelstrom workload and its options.
+if cargo build --release ; then
+    $1 test -w __NAME__ --bin ./target/release/__NAME__ --node-count 1 --time-limit 10
+else
+    echo "cargo build error"
+    return 1
+fi
+"#;
+
+/// This represents the Maelstrom test settings for a workload binary.
+struct Workload {
+    /// The name of the binary.
+    name: &'static str,
+    /// Maelstrom's name for the workload the binary is tested with,
+    /// or `None` for demos that are only run by hand with `--repl`.
+    maelstrom: Option<&'static str>,
+    /// The default number of nodes.
+    nodes: usize,
+    /// The default time limit in seconds.
+    time_limit: usize,
+    /// The default request rate, if the workload sets one.
+    rate: Option<usize>,
+    /// The other arguments passed to Maelstrom by default.
+    args: &'static [&'static str],
+}
+
+/// The binaries that can be tested, with the settings used by the scripts in `scripts/`.
+const WORKLOADS: &[Workload] = &[
+    Workload {
+        name: "echo",
+        maelstrom: Some("echo"),
+        nodes: 1,
+        time_limit: 10,
+        rate: None,
+        args: &[],
+    },
+    Workload {
+        name: "unique-ids",
+        maelstrom: Some("unique-ids"),
+        nodes: 3,
+        time_limit: 30,
+        rate: Some(1000),
+        args: &["--availability", "total", "--nemesis", "partition"],
+    },
+    Workload {
+        name: "broadcast",
+        maelstrom: Some("broadcast"),
+        nodes: 5,
+        time_limit: 20,
+        rate: Some(10),
+        args: &[],
+    },
+    Workload {
+        name: "kafka",
+        maelstrom: Some("kafka"),
+        nodes: 2,
+        time_limit: 20,
+        rate: Some(1000),
+        args: &["--concurrency", "2n"],
+    },
+    Workload {
+        name: "lin-kv",
+        maelstrom: Some("lin-kv"),
+        nodes: 3,
+        time_limit: 20,
+        rate: Some(100),
+        args: &["--concurrency", "2n", "--nemesis", "partition"],
+    },
+    Workload {
+        name: "broadcast-gossip",
+        maelstrom: Some("broadcast"),
+        nodes: 25,
+        time_limit: 20,
+        rate: Some(100),
+        args: &["--latency", "100"],
+    },
+    Workload {
+        name: "g-counter",
+        maelstrom: Some("g-counter"),
+        nodes: 3,
+        time_limit: 20,
+        rate: Some(100),
+        args: &["--nemesis", "partition"],
+    },
+    Workload {
+        name: "pn-counter",
+        maelstrom: Some("pn-counter"),
+        nodes: 3,
+        time_limit: 20,
+        rate: Some(100),
+        args: &["--nemesis", "partition"],
+    },
+    Workload {
+        name: "txn",
+        maelstrom: Some("txn-rw-register"),
+        nodes: 2,
+        time_limit: 20,
+        rate: Some(1000),
+        args: &[
+            "--concurrency",
+            "2n",
+            "--consistency-models",
+            "read-committed",
+            "--availability",
+            "total",
+            "--nemesis",
+            "partition",
+        ],
+    },
+    Workload {
+        name: "cart",
+        maelstrom: None,
+        nodes: 3,
+        time_limit: 10,
+        rate: None,
+        args: &[],
+    },
+];
+
+/// A Maelstrom run requested on the command line, of a workload that Maelstrom has.
+struct Run {
+    workload: &'static Workload,
+    nodes: usize,
+    time_limit: usize,
+    rate: Option<usize>,
+    maelstrom: Option<PathBuf>,
+    /// The arguments after `--`, passed to Maelstrom as they are.
+    extra: Vec<String>,
+}
+
+fn usage() -> String {
+    let names = WORKLOADS.iter().map(|w| w.name).collect::<Vec<_>>();
+    format!(
+        "usage: cargo vortex maelstrom <workload> [--nodes <n>] [--time-limit <s>] [--rate <r>] \
+         [--maelstrom <path>] [-- <maelstrom args>...]\n       cargo vortex new <workload>\nworkloads: {}",
+        names.join(", ")
+    )
+}
+
+/// This parses the arguments following `maelstrom`.
+fn parse(args: &[String]) -> Result<Run, String> {
+    let (name, mut rest) = args.split_first().ok_or_else(usage)?;
+    let workload = WORKLOADS
+        .iter()
+        .find(|w| w.name == name)
+        .ok_or_else(|| format!("unknown workload {}\n{}", name, usage()))?;
+    if workload.maelstrom.is_none() {
+        return Err(format!(
+            "{} has no maelstrom workload, run it with cargo run --bin {} -- --repl",
+            name, name
+        ));
+    }
+    let mut run = Run {
+        workload,
+        nodes: workload.nodes,
+        time_limit: workload.time_limit,
+        rate: workload.rate,
+        maelstrom: None,
+        extra: Vec::new(),
+    };
+    while let Some((flag, tail)) = rest.split_first() {
+        if flag == "--" {
+            run.extra = tail.to_vec();
+            break;
+        }
+        let (value, tail) = tail
+            .split_first()
+            .ok_or_else(|| format!("missing value for {}\n{}", flag, usage()))?;
+        let number = || {
+            value
+                .parse::<usize>()
+                .map_err(|_| format!("invalid value {} for {}", value, flag))
+        };
+        match flag.as_str() {
+            "--nodes" => run.nodes = number()?,
+            "--time-limit" => run.time_limit = number()?,
+            "--rate" => run.rate = Some(number()?),
+            "--maelstrom" => run.maelstrom = Some(PathBuf::from(value)),
+            _ => return Err(format!("unknown option {}\n{}", flag, usage())),
+        }
+        rest = tail;
+    }
+    Ok(run)
+}
+
+/// This scaffolds a new workload binary in the crate in the current directory:
+/// its source in `src/bin`, its entry in `Cargo.toml` and a script running it under Maelstrom.
+fn new(name: &str) -> Result<(), Box<dyn error::Error>> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err(format!(
+            "invalid workload name {:?}, use lowercase letters, digits and -",
+            name
+        )
+        .into());
+    }
+    let manifest = fs::read_to_string(MANIFEST).map_err(|e| {
+        format!(
+            "could not read {}, run this from the crate's root: {}",
+            MANIFEST, e
+        )
+    })?;
+    let bin = PathBuf::from("src/bin").join(format!("{}.rs", name));
+    let script = PathBuf::from("scripts").join(format!("{}.sh", name));
+    if bin.exists() || script.exists() || manifest.contains(&format!("name = \"{}\"", name)) {
+        return Err(format!("workload {} already exists", name).into());
+    }
+    let node = name
+        .split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect::<String>()
+        + "Node";
+    fs::write(&bin, BIN_TEMPLATE.replace("__NODE__", &node))?;
+    fs::create_dir_all("scripts")?;
+    fs::write(&script, SCRIPT_TEMPLATE.replace("__NAME__", name))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+    }
+    let mut manifest = fs::OpenOptions::new().append(true).open(MANIFEST)?;
+    write!(
+        manifest,
+        "\n[[bin]]\nname = \"{}\"\nrequired-features = [\"std\"]\n",
+        name
+    )?;
+    println!("created {} and {}", bin.display(), script.display());
+    println!(
+        "run it with ./{} <maelstrom-binary-path> or by hand with cargo run --bin {} -- --repl",
+        script.display(),
+        name
+    );
+    println!(
+        "add {} to WORKLOADS in src/bin/cargo-vortex.rs with its maelstrom settings to run it with cargo vortex maelstrom",
+        name
+    );
+    Ok(())
+}
+
+/// This finds the Maelstrom binary from the command line, `$MAELSTROM`,
+/// `./maelstrom/maelstrom` or the `PATH`, in that order.
+fn locate_maelstrom(explicit: Option<PathBuf>) -> Result<PathBuf, String> {
+    let candidates = explicit
+        .into_iter()
+        .chain(env::var_os("MAELSTROM").map(PathBuf::from))
+        .chain([PathBuf::from("maelstrom/maelstrom")])
+        .chain(
+            env::var_os("PATH")
+                .map(|paths| {
+                    env::split_paths(&paths)
+                        .map(|p| p.join("maelstrom"))
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default(),
+        );
+    for candidate in candidates {
+        if candidate.is_file() {
+            return Ok(candidate);
+        }
+    }
+    Err("maelstrom binary not found, pass --maelstrom <path> or set MAELSTROM".to_string())
+}
+
+/// This builds the workload binary in release mode, returning its path.
+fn build(name: &str) -> Result<PathBuf, Box<dyn error::Error>> {
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let status = Command::new(cargo)
+        .args(["build", "--release", "--bin", name])
+        .status()?;
+    if !status.success() {
+        return Err("cargo build error".into());
+    }
+    let target = env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("target"));
+    Ok(target.join("release").join(name))
+}
+
+/// This runs Maelstrom, echoing its output, and returns whether the test passed.
+fn test(maelstrom: &Path, bin: &Path, run: &Run) -> Result<bool, Box<dyn error::Error>> {
+    let mut command = Command::new(maelstrom);
+    command
+        .arg("test")
+        .args(["-w", run.workload.maelstrom.unwrap_or(run.workload.name)])
+        .arg("--bin")
+        .arg(bin)
+        .args(["--node-count", &run.nodes.to_string()])
+        .args(["--time-limit", &run.time_limit.to_string()]);
+    if let Some(rate) = run.rate {
+        command.args(["--rate", &rate.to_string()]);
+    }
+    command.args(run.workload.args).args(&run.extra);
+    let mut child = command.stdout(Stdio::piped()).spawn()?;
+    let mut verdict = None;
+    let mut stdout = io::stdout().lock();
+    for line in BufReader::new(child.stdout.take().ok_or("maelstrom has no stdout")?).lines() {
+        let line = line?;
+        if line.contains(PASSED) {
+            verdict = Some(true);
+        } else if line.contains(FAILED) {
+            verdict = Some(false);
+        }
+        writeln!(stdout, "{}", line)?;
+    }
+    let status = child.wait()?;
+    Ok(verdict.unwrap_or(false) && status.success())
+}
+
+/// This prints the message counts and latencies of the latest test, if its results can be read.
+fn summarize() {
+    let results = match Results::latest(STORE) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("could not read results: {}", e);
+            return;
+        }
+    };
+    let mut summary = format!(
+        "{} ops, {:.2} msgs-per-op, {:.2} server msgs-per-op",
+        results.stats.count, results.net_all.msgs_per_op, results.net_servers.msgs_per_op
+    );
+    for quantile in [0.5, 0.99, 1.0] {
+        if let Some(latency) = results.stable_latency(quantile) {
+            summary.push_str(&format!(", p{} latency {}ms", quantile * 100.0, latency));
+        }
+    }
+    println!("{}", summary);
+}
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    // Cargo runs `cargo vortex ...` as `cargo-vortex vortex ...`.
+    let args = env::args()
+        .skip(1)
+        .skip_while(|arg| arg == "vortex")
+        .collect::<Vec<_>>();
+    let run = match args.split_first() {
+        Some((command, [name])) if command == "new" => return new(name),
+        Some((command, rest)) if command == "maelstrom" => parse(rest),
+        _ => Err(usage()),
+    };
+    let run = run.unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(2);
+    });
+    let maelstrom = locate_maelstrom(run.maelstrom.clone())?;
+    let bin = build(run.workload.name)?;
+    let passed = test(&maelstrom, &bin, &run)?;
+    summarize();
+    println!(
+        "{} {} ({} nodes, {}s)",
+        if passed { "PASS" } else { "FAIL" },
+        run.workload.name,
+        run.nodes,
+        run.time_limit
+    );
+    if !passed {
+        process::exit(1);
+    }
+    Ok(())
+}
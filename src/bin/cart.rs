@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, convert::Infallible};
+use vortex::{
+    crdt::{Crdt, CrdtMap, PnCounter},
+    prelude::*,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Data {
+    Add {
+        item: String,
+        quantity: i64,
+    },
+    AddOk,
+    Remove {
+        item: String,
+    },
+    RemoveOk,
+    Read,
+    ReadOk {
+        cart: BTreeMap<String, i64>,
+    },
+    /// The whole cart of a replica, sent to its peers after every change.
+    Replicate {
+        cart: CrdtMap<String, PnCounter>,
+    },
+}
+
+/// This represents a replica of a shopping cart
+/// mapping items to their quantities which any replica can change.
+/// A removed item comes back if another replica concurrently changed its quantity.
+#[derive(Serialize)]
+struct CartNode {
+    cart: CrdtMap<String, PnCounter>,
+}
+
+impl CartNode {
+    fn new() -> Self {
+        Self {
+            cart: CrdtMap::default(),
+        }
+    }
+
+    /// This sends the cart to every other replica.
+    fn replicate(&self, ctx: &mut Context<Data>) {
+        ctx.broadcast(Data::Replicate {
+            cart: self.cart.clone(),
+        });
+    }
+}
+
+impl StateMachine<Data> for CartNode {
+    type Error = Infallible;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Data>>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        for message in messages {
+            match &message.body.payload {
+                Payload::Custom(Data::Add { item, quantity }) => {
+                    let id = ctx.node_id().to_string();
+                    self.cart
+                        .update(&id, item.clone(), |q| q.add(&id, *quantity));
+                    ctx.reply(&message, Data::AddOk);
+                    self.replicate(ctx);
+                }
+                Payload::Custom(Data::Remove { item }) => {
+                    self.cart.remove(item);
+                    ctx.reply(&message, Data::RemoveOk);
+                    self.replicate(ctx);
+                }
+                Payload::Custom(Data::Read) => {
+                    let cart = self
+                        .cart
+                        .iter()
+                        .map(|(item, q)| (item.clone(), q.value()))
+                        .collect();
+                    ctx.reply(&message, Data::ReadOk { cart });
+                }
+                Payload::Custom(Data::Replicate { cart }) => self.cart.merge(cart),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `add apple 2`, `remove apple` or `read` into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    match command {
+        ["add", item, quantity] => quantity.parse().ok().map(|quantity| Data::Add {
+            item: item.to_string(),
+            quantity,
+        }),
+        ["remove", item] => Some(Data::Remove {
+            item: item.to_string(),
+        }),
+        ["read"] => Some(Data::Read),
+        _ => None,
+    }
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(|_| CartNode::new()).with_repl(expand).run()
+}
@@ -0,0 +1,154 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{convert::Infallible, str::FromStr, time::Duration};
+use vortex::{
+    crdt::{Crdt, GCounter, PnCounter},
+    prelude::*,
+};
+
+/// The time between sending the counter to the other nodes.
+const REPLICATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// This is a trait for the counter CRDTs that a `CounterNode` replicates.
+pub trait Counter:
+    Crdt + Clone + Default + PartialEq + Serialize + DeserializeOwned + 'static
+{
+    /// The amounts added to the counter, which are also the type of its value.
+    type Delta: Copy + FromStr + Serialize + DeserializeOwned + 'static;
+
+    /// This adds the amount to the node's count.
+    fn add(&mut self, node_id: &str, delta: Self::Delta);
+
+    /// The value of the counter.
+    fn value(&self) -> Self::Delta;
+}
+
+impl Counter for GCounter {
+    type Delta = u64;
+
+    fn add(&mut self, node_id: &str, delta: u64) {
+        self.increment(node_id, delta);
+    }
+
+    fn value(&self) -> u64 {
+        GCounter::value(self)
+    }
+}
+
+impl Counter for PnCounter {
+    type Delta = i64;
+
+    fn add(&mut self, node_id: &str, delta: i64) {
+        PnCounter::add(self, node_id, delta);
+    }
+
+    fn value(&self) -> i64 {
+        PnCounter::value(self)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Data<C, D> {
+    Add {
+        delta: D,
+    },
+    AddOk,
+    Read,
+    ReadOk {
+        value: D,
+    },
+    /// The whole counter of a node, which is sent to the other nodes periodically.
+    Replicate {
+        counter: C,
+    },
+    /// The timer payload that sends the counter to the other nodes.
+    Tick,
+}
+
+/// The messages of a node replicating the counter `C`.
+pub type CounterData<C> = Data<C, <C as Counter>::Delta>;
+
+/// This represents a counter replicated to every other node periodically and merged on receipt,
+/// so that updates lost to a network partition are sent again once it heals.
+/// The `g-counter` binary replicates a `GCounter`, which is one count per node,
+/// and the `pn-counter` binary a `PnCounter`, which is one count of increments and one of decrements per node.
+#[derive(Serialize)]
+pub struct CounterNode<C: Counter> {
+    counter: C,
+    #[serde(skip)]
+    timers: Timers<CounterData<C>>,
+}
+
+impl<C: Counter> CounterNode<C> {
+    pub fn new() -> Self {
+        Self {
+            counter: C::default(),
+            timers: Timers::new(),
+        }
+    }
+
+    /// This sends the counter to every other node.
+    fn replicate(&self, ctx: &mut Context<CounterData<C>>) {
+        ctx.broadcast(Data::Replicate {
+            counter: self.counter.clone(),
+        });
+    }
+}
+
+impl<C: Counter> StateMachine<CounterData<C>> for CounterNode<C> {
+    type Error = Infallible;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<CounterData<C>>>,
+        ctx: &mut Context<CounterData<C>>,
+    ) -> Result<(), Self::Error> {
+        for message in messages {
+            match &message.body.payload {
+                Payload::Custom(Data::Add { delta }) => {
+                    self.counter.add(ctx.node_id(), *delta);
+                    ctx.reply(&message, Data::AddOk);
+                }
+                Payload::Custom(Data::Read) => {
+                    ctx.reply(
+                        &message,
+                        Data::ReadOk {
+                            value: self.counter.value(),
+                        },
+                    );
+                }
+                Payload::Custom(Data::Replicate { counter }) => self.counter.merge(counter),
+                Payload::Custom(Data::Tick)
+                    if message.src == ctx.node_id() && self.counter != C::default() =>
+                {
+                    self.replicate(ctx);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn on_init(&mut self, _node_id: &str, _node_ids: &[String]) -> Result<(), Self::Error> {
+        self.timers.every(REPLICATE_INTERVAL, Data::Tick);
+        Ok(())
+    }
+
+    fn timers(&mut self) -> Option<&mut Timers<CounterData<C>>> {
+        Some(&mut self.timers)
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `add 5` or `read` into a payload.
+pub fn expand<C: Counter>(command: &[&str]) -> Option<CounterData<C>> {
+    match command {
+        ["add", delta] => delta.parse().ok().map(|delta| Data::Add { delta }),
+        ["read"] => Some(Data::Read),
+        _ => None,
+    }
+}
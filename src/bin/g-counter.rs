@@ -0,0 +1,11 @@
+use vortex::{crdt::GCounter, prelude::*};
+
+mod counter;
+
+use counter::CounterNode;
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(|_| CounterNode::<GCounter>::new())
+        .with_repl(counter::expand::<GCounter>)
+        .run()
+}
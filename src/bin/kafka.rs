@@ -0,0 +1,471 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::Infallible,
+    time::{Duration, Instant},
+};
+use vortex::{
+    prelude::*,
+    protocol::kv::Kv,
+    protocol::FailureKind,
+    services::{KvClient, KvError, KvPayload, LinKv, Speculation},
+};
+
+/// The prefix of the keys in lin-kv holding the next offset of each log.
+const OFFSET_KEY_PREFIX: &str = "offset-";
+
+/// The maximum number of records returned for each log by a poll.
+const POLL_LIMIT: usize = 100;
+
+/// The time a missing offset is waited for before polls skip it,
+/// since an offset allocated in lin-kv may never be filled, e.g. when the reply to its allocation was lost,
+/// or only be missing on this node, e.g. when its replication was given up on.
+const GAP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The number of times a request to lin-kv or another node is retransmitted.
+const RETRIES: u32 = 10;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Data {
+    Send {
+        key: String,
+        msg: Value,
+    },
+    SendOk {
+        offset: u64,
+    },
+    Poll {
+        offsets: BTreeMap<String, u64>,
+    },
+    PollOk {
+        msgs: BTreeMap<String, Vec<(u64, Value)>>,
+    },
+    CommitOffsets {
+        offsets: BTreeMap<String, u64>,
+    },
+    CommitOffsetsOk,
+    ListCommittedOffsets {
+        keys: Vec<String>,
+    },
+    ListCommittedOffsetsOk {
+        offsets: BTreeMap<String, u64>,
+    },
+    /// A record appended by another node, which is copied to every node's log.
+    Replicate {
+        key: String,
+        offset: u64,
+        msg: Value,
+    },
+    ReplicateOk,
+    /// The requests to lin-kv and their replies.
+    #[serde(untagged)]
+    Kv(Kv),
+}
+
+impl From<Kv> for Data {
+    fn from(kv: Kv) -> Self {
+        Data::Kv(kv)
+    }
+}
+
+impl KvPayload for Data {
+    fn kv(&self) -> Option<&Kv> {
+        match self {
+            Data::Kv(kv) => Some(kv),
+            _ => None,
+        }
+    }
+}
+
+/// This represents a node of a replicated log with one append-only log per key.
+/// A single node assigns offsets itself, while several nodes allocate them from lin-kv
+/// with a compare-and-set per record, so that offsets are unique and increasing per key
+/// whichever node receives a send.
+/// Appended records and committed offsets are copied to every other node,
+/// so any node can serve polls and committed offset listings.
+#[derive(Serialize)]
+struct KafkaNode {
+    #[serde(skip)]
+    lin_kv: LinKv,
+    /// The records of each log by offset.
+    logs: HashMap<String, BTreeMap<u64, Value>>,
+    /// The committed offset of each log.
+    committed: HashMap<String, u64>,
+    /// The next offset of each log as last seen in lin-kv, from which the next allocation is attempted,
+    /// which is assumed to have moved past an allocation that failed indefinitely with `VORTEX_SPECULATE` set.
+    next_offsets: Speculation<String, u64>,
+    /// The sends waiting for an offset from lin-kv by log,
+    /// where the first send of a log is the one an offset is being allocated for.
+    #[serde(skip)]
+    sending: HashMap<String, VecDeque<Message<Data>>>,
+    /// When polls first found each missing offset of a log.
+    #[serde(skip)]
+    gaps: HashMap<(String, u64), Instant>,
+}
+
+impl KafkaNode {
+    fn new(id: &str) -> Self {
+        Self {
+            lin_kv: LinKv::new(id),
+            logs: HashMap::new(),
+            committed: HashMap::new(),
+            next_offsets: Speculation::from_env(),
+            sending: HashMap::new(),
+            gaps: HashMap::new(),
+        }
+    }
+
+    /// This appends a record to a log at the offset,
+    /// replicating it to the other nodes and replying to the send.
+    fn append(
+        &mut self,
+        request: &Message<Data>,
+        key: &str,
+        offset: u64,
+        msg: &Value,
+        ctx: &mut Context<Data>,
+    ) {
+        self.store(key, offset, msg);
+        ctx.broadcast(Data::Replicate {
+            key: key.to_string(),
+            offset,
+            msg: msg.clone(),
+        });
+        ctx.reply(request, Data::SendOk { offset });
+    }
+
+    /// This creates a compare-and-set allocating the next offset of a log in lin-kv.
+    fn allocate(&self, key: &str) -> Message<Data> {
+        let next = self.next_offsets.get(key).copied().unwrap_or_default();
+        self.lin_kv.cas(
+            format!("{}{}", OFFSET_KEY_PREFIX, key),
+            next,
+            next + 1,
+            next == 0,
+        )
+    }
+
+    /// This reads the next offset of a log from lin-kv.
+    fn read_next_offset(&self, key: &str) -> Message<Data> {
+        self.lin_kv.read(format!("{}{}", OFFSET_KEY_PREFIX, key))
+    }
+
+    /// This returns the records of each log from the polled offsets at `now`.
+    /// The records stop before the first offset that has not been replicated to this node yet,
+    /// including the polled offset itself, so that a later poll from after the returned records does not skip it,
+    /// unless the offset has been missing for `GAP_TIMEOUT`, after which it is assumed to never be filled and skipped.
+    fn poll(
+        &mut self,
+        offsets: &BTreeMap<String, u64>,
+        now: Instant,
+    ) -> BTreeMap<String, Vec<(u64, Value)>> {
+        let mut polled = BTreeMap::new();
+        for (key, offset) in offsets {
+            let Some(log) = self.logs.get(key) else {
+                continue;
+            };
+            let mut records: Vec<(u64, Value)> = Vec::new();
+            let mut expected = *offset;
+            for (offset, msg) in log.range(offset..).take(POLL_LIMIT) {
+                if *offset != expected {
+                    let found = *self.gaps.entry((key.clone(), expected)).or_insert(now);
+                    if now.duration_since(found) < GAP_TIMEOUT {
+                        break;
+                    }
+                }
+                records.push((*offset, msg.clone()));
+                expected = offset + 1;
+            }
+            polled.insert(key.clone(), records);
+        }
+        polled
+    }
+
+    /// This stores a record of a log at the offset, which is no longer missing.
+    fn store(&mut self, key: &str, offset: u64, msg: &Value) {
+        self.logs
+            .entry(key.to_string())
+            .or_default()
+            .insert(offset, msg.clone());
+        self.gaps.remove(&(key.to_string(), offset));
+    }
+
+    /// This moves the committed offsets of the logs forward, ignoring offsets behind them.
+    fn commit(&mut self, offsets: &BTreeMap<String, u64>) {
+        for (key, offset) in offsets {
+            let committed = self.committed.entry(key.clone()).or_default();
+            *committed = (*committed).max(*offset);
+        }
+    }
+
+    /// This continues allocating an offset with the result of one of its requests to lin-kv,
+    /// which may have failed indefinitely if it was given up on.
+    fn on_lin_kv(
+        &mut self,
+        request: Message<Data>,
+        result: Result<Kv, KvError>,
+        ctx: &mut Context<Data>,
+    ) {
+        let Payload::Custom(Data::Kv(request)) = request.body.payload else {
+            return;
+        };
+        let (Kv::Cas { key, .. } | Kv::Read { key }) = &request else {
+            return;
+        };
+        let Some(key) = key
+            .as_str()
+            .and_then(|key| key.strip_prefix(OFFSET_KEY_PREFIX))
+            .map(str::to_string)
+        else {
+            return;
+        };
+        match (&request, result) {
+            (Kv::Cas { from, .. }, Ok(Kv::CasOk)) => {
+                let offset = from.as_u64().unwrap_or_default();
+                self.next_offsets.confirm(key.clone(), offset + 1);
+                let sends = self.sending.entry(key.clone()).or_default();
+                if let Some(send) = sends.pop_front() {
+                    if let Payload::Custom(Data::Send { msg, .. }) = &send.body.payload {
+                        let msg = msg.clone();
+                        self.append(&send, &key, offset, &msg, ctx);
+                    }
+                }
+                if self
+                    .sending
+                    .get(&key)
+                    .is_some_and(|sends| !sends.is_empty())
+                {
+                    ctx.push(self.allocate(&key));
+                }
+            }
+            (Kv::Cas { .. }, Err(KvError::PreconditionFailed | KvError::KeyDoesNotExist)) => {
+                self.next_offsets.rollback(&key);
+                ctx.push(self.read_next_offset(&key));
+            }
+            (Kv::Read { .. }, Ok(Kv::ReadOk { value })) => {
+                self.next_offsets
+                    .confirm(key.clone(), value.as_u64().unwrap_or_default());
+                ctx.push(self.allocate(&key));
+            }
+            (Kv::Read { .. }, Err(KvError::KeyDoesNotExist)) => {
+                self.next_offsets.confirm(key.clone(), 0);
+                ctx.push(self.allocate(&key));
+            }
+            // An allocation that failed indefinitely may have taken the offset anyway,
+            // which is assumed when speculating so that the send is allocated the next offset
+            // without reading it first, and otherwise the next allocation fails its precondition
+            // and reads the next offset again.
+            // The offset assumed to be taken is never filled, so polls skip it after `GAP_TIMEOUT`.
+            (Kv::Cas { to, .. }, Err(err)) if err.failure_kind() == FailureKind::Indefinite => {
+                if let Some(to) = to.as_u64() {
+                    self.next_offsets.speculate(key.clone(), to);
+                }
+                ctx.push(self.allocate(&key));
+            }
+            _ => ctx.push(self.allocate(&key)),
+        }
+    }
+}
+
+impl StateMachine<Data> for KafkaNode {
+    type Error = Infallible;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Data>>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        for message in messages {
+            match &message.body.payload {
+                Payload::Custom(Data::Send { key, msg }) if ctx.peers().len() <= 1 => {
+                    let offset = self
+                        .logs
+                        .get(key)
+                        .and_then(|log| log.keys().next_back())
+                        .map_or(0, |offset| offset + 1);
+                    self.append(&message, key, offset, msg, ctx);
+                }
+                Payload::Custom(Data::Send { key, .. }) => {
+                    let key = key.clone();
+                    let sends = self.sending.entry(key.clone()).or_default();
+                    sends.push_back(message);
+                    if sends.len() == 1 {
+                        ctx.push(self.allocate(&key));
+                    }
+                }
+                Payload::Custom(Data::Poll { offsets }) => {
+                    let msgs = self.poll(offsets, ctx.now());
+                    ctx.reply(&message, Data::PollOk { msgs });
+                }
+                Payload::Custom(Data::CommitOffsets { offsets }) => {
+                    self.commit(offsets);
+                    if let Source::Client(_) = message.source() {
+                        ctx.broadcast(Data::CommitOffsets {
+                            offsets: offsets.clone(),
+                        });
+                    }
+                    ctx.reply(&message, Data::CommitOffsetsOk);
+                }
+                Payload::Custom(Data::ListCommittedOffsets { keys }) => {
+                    let offsets = keys
+                        .iter()
+                        .filter_map(|key| Some((key.clone(), *self.committed.get(key)?)))
+                        .collect();
+                    ctx.reply(&message, Data::ListCommittedOffsetsOk { offsets });
+                }
+                Payload::Custom(Data::Replicate { key, offset, msg }) => {
+                    self.store(key, *offset, msg);
+                    ctx.reply(&message, Data::ReplicateOk);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn expects_reply(&self, message: &Message<Data>) -> bool {
+        self.lin_kv.is_request(message)
+            || matches!(
+                message.body.payload,
+                Payload::Custom(Data::Replicate { .. } | Data::CommitOffsets { .. })
+            )
+    }
+
+    fn on_reply(
+        &mut self,
+        request: Message<Data>,
+        reply: Message<Data>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        if let Some(result) = self.lin_kv.reply(&request, &reply) {
+            self.on_lin_kv(request, result, ctx);
+        }
+        Ok(())
+    }
+
+    fn on_abandoned(
+        &mut self,
+        request: Message<Data>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        if let Some(err) = self.lin_kv.abandoned(&request) {
+            self.on_lin_kv(request, Err(err), ctx);
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Option<Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `send k1 5`, `poll k1 0`, `commit k1 3` or `list k1`
+/// into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    match command {
+        ["send", key, msg] => Some(Data::Send {
+            key: key.to_string(),
+            msg: serde_json::from_str(msg).unwrap_or_else(|_| Value::from(*msg)),
+        }),
+        ["poll", key, offset] => offset.parse().ok().map(|offset| Data::Poll {
+            offsets: BTreeMap::from([(key.to_string(), offset)]),
+        }),
+        ["commit", key, offset] => offset.parse().ok().map(|offset| Data::CommitOffsets {
+            offsets: BTreeMap::from([(key.to_string(), offset)]),
+        }),
+        ["list", keys @ ..] => Some(Data::ListCommittedOffsets {
+            keys: keys.iter().map(|k| k.to_string()).collect(),
+        }),
+        _ => None,
+    }
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(KafkaNode::new)
+        .with_repl(expand)
+        .with_retries(RetryPolicy::new(RETRIES))
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offsets(key: &str, offset: u64) -> BTreeMap<String, u64> {
+        BTreeMap::from([(key.to_string(), offset)])
+    }
+
+    fn polled(msgs: &BTreeMap<String, Vec<(u64, Value)>>, key: &str) -> Vec<u64> {
+        msgs[key].iter().map(|(offset, _)| *offset).collect()
+    }
+
+    #[test]
+    fn poll_waits_for_a_missing_offset_then_skips_it() {
+        let mut node = KafkaNode::new("n1");
+        node.store("k", 0, &Value::from(10));
+        node.store("k", 2, &Value::from(12));
+        let now = Instant::now();
+        assert_eq!(polled(&node.poll(&offsets("k", 0), now), "k"), [0]);
+        assert!(polled(&node.poll(&offsets("k", 1), now), "k").is_empty());
+        let later = now + GAP_TIMEOUT;
+        assert_eq!(polled(&node.poll(&offsets("k", 0), later), "k"), [0, 2]);
+        assert_eq!(polled(&node.poll(&offsets("k", 1), later), "k"), [2]);
+    }
+
+    /// This returns the requests that the node sent to lin-kv in the context.
+    fn lin_kv_requests(ctx: Context<Data>) -> Vec<Message<Data>> {
+        ctx.into_messages()
+            .into_iter()
+            .filter(|message| matches!(message.body.payload, Payload::Custom(Data::Kv(_))))
+            .collect()
+    }
+
+    #[test]
+    fn poll_skips_a_speculated_offset() {
+        let peers = ["n1".to_string(), "n2".to_string()];
+        let now = Instant::now();
+        let mut node = KafkaNode::new("n1");
+        node.next_offsets = Speculation::new();
+        let mut send = Message::new(
+            "c1",
+            "n1",
+            Data::Send {
+                key: "k".into(),
+                msg: Value::from(10),
+            },
+        );
+        send.body.msg_id = Some(1);
+        let mut ctx = Context::new("n1", &peers, now);
+        node.apply(vec![send], &mut ctx).unwrap();
+        let [first] = <[_; 1]>::try_from(lin_kv_requests(ctx)).unwrap();
+
+        // The allocation of offset 0 is given up on, so offset 1 is allocated assuming it took 0.
+        let mut ctx = Context::new("n1", &peers, now);
+        node.on_lin_kv(first, Err(KvError::Abandoned), &mut ctx);
+        let [second] = <[_; 1]>::try_from(lin_kv_requests(ctx)).unwrap();
+        assert!(matches!(
+            &second.body.payload,
+            Payload::Custom(Data::Kv(Kv::Cas { from, .. })) if from == 1
+        ));
+        let mut ctx = Context::new("n1", &peers, now);
+        node.on_lin_kv(second, Ok(Kv::CasOk), &mut ctx);
+
+        assert!(polled(&node.poll(&offsets("k", 0), now), "k").is_empty());
+        let later = now + GAP_TIMEOUT;
+        assert_eq!(polled(&node.poll(&offsets("k", 0), later), "k"), [1]);
+    }
+
+    #[test]
+    fn poll_returns_a_late_offset_before_the_timeout() {
+        let mut node = KafkaNode::new("n1");
+        node.store("k", 0, &Value::from(10));
+        node.store("k", 2, &Value::from(12));
+        let now = Instant::now();
+        assert_eq!(polled(&node.poll(&offsets("k", 0), now), "k"), [0]);
+        node.store("k", 1, &Value::from(11));
+        assert_eq!(polled(&node.poll(&offsets("k", 0), now), "k"), [0, 1, 2]);
+    }
+}
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+use std::{convert::Infallible, env, time::Duration};
+use vortex::{
+    prelude::*,
+    protocol::{kv::Kv, ErrorCode},
+    raft::{NotLeader, Raft, RaftMessage, Replicated},
+    runtime::clock,
+    services::kv::KvStore,
+    storage::{self, Json, SnapshotError, STATE_DIR_ENV},
+};
+
+/// The time between checks for elections and heartbeats,
+/// which must be well below `raft::HEARTBEAT_INTERVAL`.
+const TICK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A client request to the store, replicated through the log along with where its reply goes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Command {
+    op: Kv,
+    client: String,
+    msg_id: Option<usize>,
+    /// The node that received the request, which is the one that replies once it is applied.
+    node: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Data {
+    /// A client request sent to the leader by a follower that received it.
+    Forward { command: Command },
+    /// The timer payload that drives elections and heartbeats.
+    Tick,
+    #[serde(untagged)]
+    Raft(RaftMessage<Command>),
+    /// The client requests and their replies.
+    #[serde(untagged)]
+    Kv(Kv),
+}
+
+/// The key-value store replicated through the log,
+/// which answers each command as Maelstrom's own key-value services would.
+#[derive(Default, Serialize)]
+struct Store {
+    #[serde(skip)]
+    kv: KvStore,
+}
+
+impl Replicated for Store {
+    type Command = Command;
+    type Output = (Command, Option<Payload<Kv>>);
+
+    fn apply(&mut self, command: Command) -> Self::Output {
+        let reply = self.kv.handle(&command.client, command.op.clone());
+        (command, reply)
+    }
+}
+
+/// This represents a node of a linearizable key-value store replicated with Raft.
+/// Every request, including reads, goes through the leader's log,
+/// so it takes effect at a single point between being received and being answered.
+/// Followers forward requests to the leader they know of.
+/// The hard state is persisted and restored on restart if `VORTEX_STATE_DIR` is set.
+#[derive(Serialize)]
+struct LinKvNode {
+    id: String,
+    /// The Raft node, which is created once the cluster is known.
+    raft: Option<Raft<Store>>,
+    /// The commit index restored after a restart,
+    /// up to which the entries were answered before the restart and are only applied again.
+    restored: u64,
+    #[serde(skip)]
+    persist: bool,
+    #[serde(skip)]
+    timers: Timers<Data>,
+}
+
+impl LinKvNode {
+    fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            raft: None,
+            restored: 0,
+            persist: env::var_os(STATE_DIR_ENV).is_some(),
+            timers: Timers::new(),
+        }
+    }
+}
+
+impl StateMachine<Data> for LinKvNode {
+    type Error = SnapshotError;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Data>>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        let Some(raft) = &mut self.raft else {
+            return Ok(());
+        };
+        let now = ctx.now();
+        let mut outgoing = Vec::new();
+        for message in messages {
+            let op = match &message.body.payload {
+                Payload::Custom(Data::Kv(op)) => op.clone(),
+                #[cfg(feature = "kv")]
+                Payload::Kv(op) => op.clone(),
+                Payload::Custom(Data::Forward { command }) => {
+                    // A request forwarded to a node that lost leadership is left for the client to time out.
+                    let _ = raft.propose(command.clone());
+                    continue;
+                }
+                Payload::Custom(Data::Raft(raft_message)) => {
+                    outgoing.extend(raft.handle(&message.src, raft_message.clone(), now));
+                    continue;
+                }
+                _ => continue,
+            };
+            if !matches!(op, Kv::Read { .. } | Kv::Write { .. } | Kv::Cas { .. }) {
+                continue;
+            }
+            let command = Command {
+                op,
+                client: message.src.clone(),
+                msg_id: message.body.msg_id,
+                node: self.id.clone(),
+            };
+            match raft.propose(command.clone()) {
+                Ok(_) => {}
+                Err(NotLeader {
+                    leader: Some(leader),
+                }) => ctx.send(&leader, Data::Forward { command }),
+                Err(NotLeader { leader: None }) => ctx.push(
+                    message.error_reply(ErrorCode::TemporarilyUnavailable, "no leader is known"),
+                ),
+            }
+        }
+        outgoing.extend(raft.poll(now));
+        for (dest, raft_message) in outgoing {
+            ctx.send(&dest, Data::Raft(raft_message));
+        }
+        for (index, (command, reply)) in raft.apply_committed() {
+            let Some(reply) = reply else {
+                continue;
+            };
+            if command.node != self.id || index <= self.restored {
+                continue;
+            }
+            let Ok(payload) = reply.try_map(|kv| Ok::<_, Infallible>(Data::Kv(kv)));
+            let mut reply = Message::new(&self.id, &command.client, payload);
+            reply.body.in_reply_to = command.msg_id;
+            ctx.push(reply);
+        }
+        if raft.take_changed() && self.persist {
+            storage::save(
+                &Json,
+                raft.hard_state(),
+                storage::state_path(&self.id, "raft"),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn on_init(&mut self, node_id: &str, node_ids: &[String]) -> Result<(), Self::Error> {
+        let mut raft = Raft::new(node_id, node_ids, Store::default(), clock::now());
+        let path = storage::state_path(node_id, "raft");
+        if self.persist && path.exists() {
+            raft = raft.restore(storage::load(&Json, path)?);
+            self.restored = raft.commit_index();
+        }
+        self.raft = Some(raft);
+        self.timers.every(TICK_INTERVAL, Data::Tick);
+        Ok(())
+    }
+
+    fn priority(&self, message: &Message<Data>) -> Priority {
+        match &message.body.payload {
+            Payload::Custom(Data::Raft(raft_message)) => raft_message.priority(),
+            _ => Priority::Normal,
+        }
+    }
+
+    fn timers(&mut self) -> Option<&mut Timers<Data>> {
+        Some(&mut self.timers)
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `read 1`, `write 1 5` or `cas 1 5 6` into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    let value = |v: &str| serde_json::from_str(v).ok();
+    let kv = match command {
+        ["read", key] => Kv::Read { key: value(key)? },
+        ["write", key, v] => Kv::Write {
+            key: value(key)?,
+            value: value(v)?,
+        },
+        ["cas", key, from, to] => Kv::Cas {
+            key: value(key)?,
+            from: value(from)?,
+            to: value(to)?,
+            create_if_not_exists: false,
+        },
+        _ => return None,
+    };
+    Some(Data::Kv(kv))
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(LinKvNode::new).with_repl(expand).run()
+}
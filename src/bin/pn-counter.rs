@@ -0,0 +1,11 @@
+use vortex::{crdt::PnCounter, prelude::*};
+
+mod counter;
+
+use counter::CounterNode;
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(|_| CounterNode::<PnCounter>::new())
+        .with_repl(counter::expand::<PnCounter>)
+        .run()
+}
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{collections::BTreeMap, convert::Infallible};
+use vortex::prelude::*;
+
+/// The number of times the writes of a transaction are retransmitted to a node that has not acknowledged them.
+const REPLICATE_RETRIES: u32 = 20;
+
+/// A micro-operation of a transaction as `[f, key, value]`,
+/// where `f` is `r` for a read with a `null` value until it is executed or `w` for a write.
+type Op = (String, u64, Option<Value>);
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Data {
+    Txn {
+        txn: Vec<Op>,
+    },
+    TxnOk {
+        txn: Vec<Op>,
+    },
+    /// The writes of a transaction executed by another node, applied together.
+    Replicate {
+        writes: Vec<(u64, Value)>,
+    },
+    ReplicateOk,
+}
+
+/// This represents a node of a totally available key-value store.
+/// Every transaction executes atomically against the node's own registers without coordination,
+/// and its writes are then sent to every other node, which applies them all at once,
+/// so that no node ever observes part of a transaction.
+#[derive(Serialize)]
+struct TxnNode {
+    registers: BTreeMap<u64, Value>,
+}
+
+impl TxnNode {
+    fn new() -> Self {
+        Self {
+            registers: BTreeMap::new(),
+        }
+    }
+
+    /// This executes the micro-operations in order, returning them with the values read,
+    /// along with the final value written to each register.
+    fn execute(&mut self, txn: &[Op]) -> (Vec<Op>, Vec<(u64, Value)>) {
+        let mut writes = BTreeMap::new();
+        let ops = txn
+            .iter()
+            .map(|(f, key, value)| match f.as_str() {
+                "r" => (f.clone(), *key, self.registers.get(key).cloned()),
+                _ => {
+                    if let Some(value) = value {
+                        self.registers.insert(*key, value.clone());
+                        writes.insert(*key, value.clone());
+                    }
+                    (f.clone(), *key, value.clone())
+                }
+            })
+            .collect();
+        (ops, writes.into_iter().collect())
+    }
+}
+
+impl StateMachine<Data> for TxnNode {
+    type Error = Infallible;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Data>>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        for message in messages {
+            match &message.body.payload {
+                Payload::Custom(Data::Txn { txn }) => {
+                    let (txn, writes) = self.execute(txn);
+                    if !writes.is_empty() {
+                        ctx.broadcast(Data::Replicate { writes });
+                    }
+                    ctx.reply(&message, Data::TxnOk { txn });
+                }
+                Payload::Custom(Data::Replicate { writes }) => {
+                    self.registers
+                        .extend(writes.iter().map(|(k, v)| (*k, v.clone())));
+                    ctx.reply(&message, Data::ReplicateOk);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn expects_reply(&self, message: &Message<Data>) -> bool {
+        matches!(
+            message.body.payload,
+            Payload::Custom(Data::Replicate { .. })
+        )
+    }
+
+    fn snapshot(&self) -> Option<Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `txn r 1 w 1 5 r 1` into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    let ["txn", ops @ ..] = command else {
+        return None;
+    };
+    let mut ops = ops;
+    let mut txn = Vec::new();
+    while !ops.is_empty() {
+        match ops {
+            ["r", key, rest @ ..] => {
+                txn.push(("r".to_string(), key.parse().ok()?, None));
+                ops = rest;
+            }
+            ["w", key, value, rest @ ..] => {
+                let value = serde_json::from_str(value).ok()?;
+                txn.push(("w".to_string(), key.parse().ok()?, Some(value)));
+                ops = rest;
+            }
+            _ => return None,
+        }
+    }
+    Some(Data::Txn { txn })
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(|_| TxnNode::new())
+        .with_repl(expand)
+        .with_retries(RetryPolicy::new(REPLICATE_RETRIES))
+        .run()
+}
@@ -1,80 +1,287 @@
 use serde::{Deserialize, Serialize};
-use std::{
-    error,
-    io::{self, BufRead},
+use std::{collections::VecDeque, env};
+use vortex::{
+    id::IdGenerator,
+    prelude::*,
+    protocol::kv::Kv,
+    services::{KvClient, KvError, KvPayload, SeqKv},
+    storage::{self, SnapshotError},
 };
-use vortex::{Message, MessageError, Node, Payload, StateMachine};
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The environment variable selecting how IDs are generated:
+/// `blocks` leases blocks of numeric IDs from seq-kv,
+/// and anything else generates IDs from the node's ID and persisted generation.
+const STRATEGY_ENV: &str = "VORTEX_UNIQUE_IDS";
+
+/// The number of IDs leased from seq-kv at a time.
+const BLOCK_SIZE: u64 = 1000;
+
+/// The key in seq-kv holding the first ID that has not been leased.
+const BLOCK_KEY: &str = "unique-ids";
+
+/// The number of times a request to seq-kv is retransmitted.
+const SEQ_KV_RETRIES: u32 = 5;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 enum Data {
     Generate {
-        msg_id: usize,
+        /// The number of IDs requested by a batch generate.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        count: Option<usize>,
     },
     GenerateOk {
-        msg_id: usize,
-        in_reply_to: usize,
-        id: String,
+        /// The ID generated for a single generate.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+        /// The IDs generated for a batch generate.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ids: Option<Vec<String>>,
     },
+    /// The requests to seq-kv and their replies.
+    #[serde(untagged)]
+    Kv(Kv),
+}
+
+impl From<Kv> for Data {
+    fn from(kv: Kv) -> Self {
+        Data::Kv(kv)
+    }
 }
 
+impl KvPayload for Data {
+    fn kv(&self) -> Option<&Kv> {
+        match self {
+            Data::Kv(kv) => Some(kv),
+            _ => None,
+        }
+    }
+}
+
+/// This represents the IDs leased from seq-kv, which are numbers unique across the cluster
+/// as every lease moves the first unleased ID in seq-kv forward with a compare-and-set.
+/// IDs left in a block when the node crashes are never used, but never reused either.
+#[derive(Default, Serialize)]
+struct Blocks {
+    /// The next ID of the leased block.
+    next: u64,
+    /// The end of the leased block.
+    end: u64,
+    /// The first unleased ID as last seen in seq-kv, from which the next lease is attempted.
+    counter: u64,
+    /// Whether a lease is awaiting a reply from seq-kv.
+    leasing: bool,
+    /// The generate requests waiting for a block with enough IDs.
+    #[serde(skip)]
+    waiting: VecDeque<Message<Data>>,
+}
+
+impl Blocks {
+    /// The number of IDs the first waiting request needs.
+    fn needed(&self) -> u64 {
+        match self.waiting.front().map(|request| &request.body.payload) {
+            Some(Payload::Custom(Data::Generate { count: Some(count) })) => *count as u64,
+            _ => 1,
+        }
+    }
+
+    /// This replies to the waiting requests while the block has enough IDs,
+    /// and leases another block if any requests are still waiting.
+    fn serve(&mut self, seq_kv: &SeqKv, ctx: &mut Context<Data>) {
+        while !self.waiting.is_empty() && self.end - self.next >= self.needed() {
+            let needed = self.needed();
+            let Some(request) = self.waiting.pop_front() else {
+                break;
+            };
+            let mut ids: Vec<String> = (self.next..self.next + needed)
+                .map(|id| id.to_string())
+                .collect();
+            self.next += needed;
+            let reply = match request.body.payload {
+                Payload::Custom(Data::Generate { count: Some(_) }) => Data::GenerateOk {
+                    id: None,
+                    ids: Some(ids),
+                },
+                _ => Data::GenerateOk {
+                    id: ids.pop(),
+                    ids: None,
+                },
+            };
+            ctx.reply(&request, reply);
+        }
+        if !self.waiting.is_empty() && !self.leasing {
+            self.leasing = true;
+            ctx.push(self.lease(seq_kv));
+        }
+    }
+
+    /// This creates a compare-and-set moving the first unleased ID in seq-kv past a new block.
+    fn lease(&self, seq_kv: &SeqKv) -> Message<Data> {
+        let size = self.needed().max(BLOCK_SIZE);
+        seq_kv.cas(
+            BLOCK_KEY,
+            self.counter,
+            self.counter + size,
+            self.counter == 0,
+        )
+    }
+
+    /// This continues a lease with the result of one of its requests to seq-kv.
+    fn on_reply(
+        &mut self,
+        seq_kv: &SeqKv,
+        request: &Kv,
+        reply: Result<Kv, KvError>,
+        ctx: &mut Context<Data>,
+    ) {
+        match (request, reply) {
+            (Kv::Cas { from, to, .. }, Ok(Kv::CasOk)) => {
+                self.next = from.as_u64().unwrap_or_default();
+                self.end = to.as_u64().unwrap_or_default();
+                self.counter = self.end;
+                self.leasing = false;
+            }
+            (Kv::Cas { .. }, Err(KvError::PreconditionFailed | KvError::KeyDoesNotExist)) => {
+                ctx.push(seq_kv.read(BLOCK_KEY));
+                return;
+            }
+            (Kv::Read { .. }, Ok(Kv::ReadOk { value })) => {
+                self.counter = value.as_u64().unwrap_or_default();
+                ctx.push(self.lease(seq_kv));
+                return;
+            }
+            (Kv::Read { .. }, Err(KvError::KeyDoesNotExist)) => {
+                self.counter = 0;
+                ctx.push(self.lease(seq_kv));
+                return;
+            }
+            // A lease that failed indefinitely may have leased the block anyway,
+            // in which case the next lease fails its precondition and reads the counter again.
+            _ => self.leasing = false,
+        }
+        self.serve(seq_kv, ctx);
+    }
+}
+
+#[derive(Serialize)]
 struct UniqueIdsNode {
     id: String,
-    msg_id_counter: usize,
+    #[serde(skip)]
+    seq_kv: SeqKv,
+    /// The generator is resumed from the node's persisted generation once the node is initialized.
+    ids: Option<IdGenerator>,
+    /// The IDs leased from seq-kv, which are used instead of the generator if selected.
+    blocks: Option<Blocks>,
 }
 
 impl UniqueIdsNode {
     fn new(id: &str) -> Self {
+        let blocks = env::var(STRATEGY_ENV).is_ok_and(|strategy| strategy == "blocks");
         Self {
             id: id.to_string(),
-            msg_id_counter: 0,
+            seq_kv: SeqKv::new(id),
+            ids: None,
+            blocks: blocks.then(Blocks::default),
         }
     }
 }
 
 impl StateMachine<Data> for UniqueIdsNode {
+    type Error = SnapshotError;
+
     fn apply(
         &mut self,
         messages: Vec<Message<Data>>,
-    ) -> Result<Vec<Message<Data>>, Box<dyn error::Error>> {
-        let mut responses = Vec::new();
-        for Message { src, dest, body } in messages {
-            if let Payload::Custom(Data::Generate { msg_id }) = body {
-                self.msg_id_counter += 1;
-                responses.push(Message {
-                    src: dest,
-                    dest: src,
-                    body: Payload::Custom(Data::GenerateOk {
-                        msg_id: self.msg_id_counter,
-                        in_reply_to: msg_id,
-                        id: format!("{}/{}", self.id, self.msg_id_counter),
-                    }),
-                });
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        if let Some(blocks) = &mut self.blocks {
+            blocks
+                .waiting
+                .extend(messages.into_iter().filter(|message| {
+                    matches!(message.body.payload, Payload::Custom(Data::Generate { .. }))
+                }));
+            blocks.serve(&self.seq_kv, ctx);
+            return Ok(());
+        }
+        let Some(ids) = &mut self.ids else {
+            return Ok(());
+        };
+        for message in messages {
+            if let Payload::Custom(Data::Generate { count }) = message.body.payload {
+                let (id, ids) = match count {
+                    Some(count) => (None, Some(ids.next_ids(count))),
+                    None => (Some(ids.next_id()), None),
+                };
+                ctx.reply(&message, Data::GenerateOk { id, ids });
             }
         }
-        Ok(responses)
+        Ok(())
+    }
+
+    fn on_init(&mut self, node_id: &str, _node_ids: &[String]) -> Result<(), Self::Error> {
+        if self.blocks.is_none() {
+            let path = storage::state_path(node_id, "generation");
+            self.ids = Some(IdGenerator::resume(node_id, path)?);
+        }
+        Ok(())
+    }
+
+    fn expects_reply(&self, message: &Message<Data>) -> bool {
+        self.seq_kv.is_request(message)
+    }
+
+    fn on_reply(
+        &mut self,
+        request: Message<Data>,
+        reply: Message<Data>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        if let (Some(blocks), Payload::Custom(Data::Kv(kv))) =
+            (&mut self.blocks, &request.body.payload)
+        {
+            if let Some(result) = self.seq_kv.reply(&request, &reply) {
+                blocks.on_reply(&self.seq_kv, kv, result, ctx);
+            }
+        }
+        Ok(())
     }
-}
 
-fn main() -> Result<(), Box<dyn error::Error>> {
-    let mut stdin = io::stdin().lock();
-    let mut stdout = io::stdout().lock();
-
-    let init: Message<Data> = Message::from_reader(&mut stdin)?;
-    let id = match &init.body {
-        Payload::Init { node_id, .. } => Ok(node_id.to_string()),
-        _ => Err(MessageError::Invalid),
-    }?;
-    let (mut node, resp) = Node::init(init, Box::new(UniqueIdsNode::new(&id)))?;
-    resp.write(&mut stdout)?;
-
-    for line in stdin.lines() {
-        let message: Message<Data> = Message::from_str(&line?)?;
-        let responses = node.recv_messages(vec![message])?;
-        for res in responses {
-            res.write(&mut stdout)?;
+    fn on_abandoned(
+        &mut self,
+        request: Message<Data>,
+        ctx: &mut Context<Data>,
+    ) -> Result<(), Self::Error> {
+        if let (Some(blocks), Payload::Custom(Data::Kv(kv))) =
+            (&mut self.blocks, &request.body.payload)
+        {
+            if let Some(err) = self.seq_kv.abandoned(&request) {
+                blocks.on_reply(&self.seq_kv, kv, Err(err), ctx);
+            }
         }
+        Ok(())
     }
-    Ok(())
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        serde_json::to_value(self).ok()
+    }
+}
+
+/// This expands a REPL command such as `generate` or `generate 5` into a payload.
+fn expand(command: &[&str]) -> Option<Data> {
+    match command {
+        ["generate"] => Some(Data::Generate { count: None }),
+        ["generate", count] => count
+            .parse()
+            .ok()
+            .map(|count| Data::Generate { count: Some(count) }),
+        _ => None,
+    }
+}
+
+fn main() -> Result<(), vortex::Error> {
+    Runtime::new(UniqueIdsNode::new)
+        .with_repl(expand)
+        .with_retries(RetryPolicy::new(SEQ_KV_RETRIES))
+        .run()
 }
@@ -0,0 +1,432 @@
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::{String, ToString},
+};
+use serde::{Deserialize, Serialize};
+
+/// This is a trait for state-based conflict-free replicated data types,
+/// whose replicas converge to the same state once they have merged each other's states,
+/// regardless of the order or the number of times the states are merged.
+pub trait Crdt {
+    /// This merges another replica's state into this one.
+    /// Merging must be commutative, associative and idempotent.
+    fn merge(&mut self, other: &Self);
+}
+
+/// This represents a single event on a replica as the replica's node ID and a counter.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Dot(pub String, pub u64);
+
+/// This represents the events a replica has seen as the highest counter seen from each node,
+/// which suffices since every replica's events are generated and merged in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CausalContext(BTreeMap<String, u64>);
+
+impl CausalContext {
+    /// This records a new event on the node's replica and returns its dot.
+    pub fn next(&mut self, node_id: &str) -> Dot {
+        let counter = self.0.entry(node_id.to_string()).or_default();
+        *counter += 1;
+        Dot(node_id.to_string(), *counter)
+    }
+
+    /// Whether the event has been seen.
+    pub fn contains(&self, dot: &Dot) -> bool {
+        self.0.get(&dot.0).is_some_and(|&c| dot.1 <= c)
+    }
+}
+
+impl Crdt for CausalContext {
+    fn merge(&mut self, other: &Self) {
+        for (node, &counter) in &other.0 {
+            let c = self.0.entry(node.clone()).or_default();
+            *c = (*c).max(counter);
+        }
+    }
+}
+
+/// This merges the dots supporting an element on two replicas.
+/// A dot survives if both replicas have it, or if the replica without it has never seen it,
+/// i.e. it is dropped if one replica has removed it.
+fn merge_dots(
+    ours: Option<&BTreeSet<Dot>>,
+    our_context: &CausalContext,
+    theirs: Option<&BTreeSet<Dot>>,
+    their_context: &CausalContext,
+) -> BTreeSet<Dot> {
+    let empty = BTreeSet::new();
+    let (ours, theirs) = (ours.unwrap_or(&empty), theirs.unwrap_or(&empty));
+    let kept_ours = ours
+        .iter()
+        .filter(|d| theirs.contains(d) || !their_context.contains(d));
+    let kept_theirs = theirs
+        .iter()
+        .filter(|d| !ours.contains(d) && !our_context.contains(d));
+    kept_ours.chain(kept_theirs).cloned().collect()
+}
+
+/// This represents a counter that can only be incremented.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GCounter(BTreeMap<String, u64>);
+
+impl GCounter {
+    pub fn increment(&mut self, node_id: &str, n: u64) {
+        *self.0.entry(node_id.to_string()).or_default() += n;
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0.values().sum()
+    }
+}
+
+impl Crdt for GCounter {
+    fn merge(&mut self, other: &Self) {
+        for (node, &n) in &other.0 {
+            let c = self.0.entry(node.clone()).or_default();
+            *c = (*c).max(n);
+        }
+    }
+}
+
+/// This represents a counter that can be incremented and decremented,
+/// as a pair of counters for the increments and the decrements.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PnCounter {
+    increments: GCounter,
+    decrements: GCounter,
+}
+
+impl PnCounter {
+    pub fn add(&mut self, node_id: &str, delta: i64) {
+        if delta >= 0 {
+            self.increments.increment(node_id, delta.unsigned_abs());
+        } else {
+            self.decrements.increment(node_id, delta.unsigned_abs());
+        }
+    }
+
+    pub fn value(&self) -> i64 {
+        self.increments.value() as i64 - self.decrements.value() as i64
+    }
+}
+
+impl Crdt for PnCounter {
+    fn merge(&mut self, other: &Self) {
+        self.increments.merge(&other.increments);
+        self.decrements.merge(&other.decrements);
+    }
+}
+
+/// This represents a register where the write with the highest timestamp wins,
+/// with ties broken by the ID of the node that wrote it.
+/// The timestamps come from the caller, e.g. an `hlc::Timestamp` packed into a `u64`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LwwRegister<T> {
+    value: Option<T>,
+    timestamp: (u64, String),
+}
+
+impl<T> Default for LwwRegister<T> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            timestamp: (0, String::new()),
+        }
+    }
+}
+
+impl<T> LwwRegister<T> {
+    /// This writes the value unless a write with a higher timestamp has been seen.
+    pub fn set(&mut self, node_id: &str, timestamp: u64, value: T) {
+        let timestamp = (timestamp, node_id.to_string());
+        if timestamp > self.timestamp {
+            self.value = Some(value);
+            self.timestamp = timestamp;
+        }
+    }
+
+    pub fn get(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+}
+
+impl<T> Crdt for LwwRegister<T>
+where
+    T: Clone,
+{
+    fn merge(&mut self, other: &Self) {
+        if other.timestamp > self.timestamp {
+            self.value = other.value.clone();
+            self.timestamp = other.timestamp.clone();
+        }
+    }
+}
+
+/// This represents an observed-remove set, where an add wins over a concurrent remove.
+/// Each add is tagged with a dot so that a remove only removes the adds it has seen.
+/// Elements are keys of a JSON object when serialized, so they should be strings or integers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize + Ord",
+    deserialize = "T: Deserialize<'de> + Ord"
+))]
+pub struct OrSet<T> {
+    elements: BTreeMap<T, BTreeSet<Dot>>,
+    context: CausalContext,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        Self {
+            elements: BTreeMap::new(),
+            context: CausalContext::default(),
+        }
+    }
+}
+
+impl<T> OrSet<T>
+where
+    T: Ord,
+{
+    pub fn add(&mut self, node_id: &str, element: T) {
+        let dot = self.context.next(node_id);
+        self.elements.insert(element, BTreeSet::from([dot]));
+    }
+
+    pub fn remove(&mut self, element: &T) {
+        self.elements.remove(element);
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.elements.contains_key(element)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.elements.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+}
+
+impl<T> Crdt for OrSet<T>
+where
+    T: Ord + Clone,
+{
+    fn merge(&mut self, other: &Self) {
+        let keys = self
+            .elements
+            .keys()
+            .chain(other.elements.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        for key in keys {
+            let dots = merge_dots(
+                self.elements.get(&key),
+                &self.context,
+                other.elements.get(&key),
+                &other.context,
+            );
+            if dots.is_empty() {
+                self.elements.remove(&key);
+            } else {
+                self.elements.insert(key, dots);
+            }
+        }
+        self.context.merge(&other.context);
+    }
+}
+
+/// This represents an entry of a `CrdtMap` with the dots of the updates that keep it in the map.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry<V> {
+    dots: BTreeSet<Dot>,
+    value: V,
+}
+
+/// This represents a map whose values are themselves CRDTs, such as a shopping cart of counters.
+/// Each entry keeps the dots of the updates to it, so that removing an entry only removes
+/// the updates the remover has seen, and an update wins over a concurrent remove.
+/// An entry that survives a concurrent remove keeps the state merged from all of its updates,
+/// while an entry added again after a remove starts over, as the state of the updates removed is left out.
+/// Keys are keys of a JSON object when serialized, so they should be strings or integers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "K: Serialize + Ord, V: Serialize",
+    deserialize = "K: Deserialize<'de> + Ord, V: Deserialize<'de>"
+))]
+pub struct CrdtMap<K, V> {
+    entries: BTreeMap<K, Entry<V>>,
+    context: CausalContext,
+}
+
+impl<K, V> Default for CrdtMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            context: CausalContext::default(),
+        }
+    }
+}
+
+impl<K, V> CrdtMap<K, V>
+where
+    K: Ord,
+    V: Crdt + Default,
+{
+    /// This updates the value of the key on the node's replica,
+    /// starting from the default value if the key is not in the map.
+    pub fn update(&mut self, node_id: &str, key: K, f: impl FnOnce(&mut V)) {
+        let dot = self.context.next(node_id);
+        let entry = self.entries.entry(key).or_insert_with(|| Entry {
+            dots: BTreeSet::new(),
+            value: V::default(),
+        });
+        entry.dots = BTreeSet::from([dot]);
+        f(&mut entry.value);
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        self.entries.remove(key);
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|e| &e.value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(k, e)| (k, &e.value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<K, V> Crdt for CrdtMap<K, V>
+where
+    K: Ord + Clone,
+    V: Crdt + Clone,
+{
+    fn merge(&mut self, other: &Self) {
+        let keys = self
+            .entries
+            .keys()
+            .chain(other.entries.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>();
+        for key in keys {
+            let ours = self.entries.get(&key);
+            let theirs = other.entries.get(&key);
+            let dots = merge_dots(
+                ours.map(|e| &e.dots),
+                &self.context,
+                theirs.map(|e| &e.dots),
+                &other.context,
+            );
+            if dots.is_empty() {
+                self.entries.remove(&key);
+                continue;
+            }
+            // The value of a replica whose dots were all removed is from before the remove.
+            let survives = |entry: &Entry<V>| entry.dots.iter().any(|dot| dots.contains(dot));
+            let value = match (ours.filter(|e| survives(e)), theirs.filter(|e| survives(e))) {
+                (Some(ours), Some(theirs)) => {
+                    let mut value = ours.value.clone();
+                    value.merge(&theirs.value);
+                    value
+                }
+                (Some(e), None) | (None, Some(e)) => e.value.clone(),
+                (None, None) => continue,
+            };
+            self.entries.insert(key, Entry { dots, value });
+        }
+        self.context.merge(&other.context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// This merges the replicas into each other, checking that they converge.
+    fn sync<T>(a: &mut T, b: &mut T)
+    where
+        T: Crdt + Clone + PartialEq + core::fmt::Debug,
+    {
+        let theirs = b.clone();
+        b.merge(a);
+        a.merge(&theirs);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn crdt_map_starts_over_when_added_again_after_a_remove() {
+        let mut a: CrdtMap<String, PnCounter> = CrdtMap::default();
+        let mut b = CrdtMap::default();
+        a.update("n1", "apple".into(), |q| q.add("n1", 2));
+        sync(&mut a, &mut b);
+        a.remove(&"apple".to_string());
+        a.update("n1", "apple".into(), |q| q.add("n1", 1));
+        sync(&mut a, &mut b);
+        assert_eq!(a.get(&"apple".to_string()).map(PnCounter::value), Some(1));
+    }
+
+    #[test]
+    fn crdt_map_stays_removed_after_merging_the_removed_state() {
+        let mut a: CrdtMap<String, PnCounter> = CrdtMap::default();
+        let mut b = CrdtMap::default();
+        a.update("n1", "apple".into(), |q| q.add("n1", 2));
+        sync(&mut a, &mut b);
+        a.remove(&"apple".to_string());
+        sync(&mut a, &mut b);
+        assert!(a.is_empty());
+    }
+
+    #[test]
+    fn crdt_map_update_wins_over_a_concurrent_remove() {
+        let mut a: CrdtMap<String, PnCounter> = CrdtMap::default();
+        let mut b = CrdtMap::default();
+        a.update("n1", "apple".into(), |q| q.add("n1", 2));
+        sync(&mut a, &mut b);
+        a.remove(&"apple".to_string());
+        b.update("n2", "apple".into(), |q| q.add("n2", 1));
+        sync(&mut a, &mut b);
+        assert_eq!(a.get(&"apple".to_string()).map(PnCounter::value), Some(3));
+    }
+
+    #[test]
+    fn or_set_add_wins_over_a_concurrent_remove() {
+        let mut a = OrSet::default();
+        let mut b = OrSet::default();
+        a.add("n1", 1);
+        sync(&mut a, &mut b);
+        a.remove(&1);
+        b.add("n2", 1);
+        sync(&mut a, &mut b);
+        assert!(a.contains(&1));
+        a.remove(&1);
+        sync(&mut a, &mut b);
+        assert!(b.is_empty());
+    }
+
+    #[test]
+    fn counters_merge_idempotently() {
+        let mut a = PnCounter::default();
+        let mut b = PnCounter::default();
+        a.add("n1", 5);
+        b.add("n2", -2);
+        sync(&mut a, &mut b);
+        sync(&mut a, &mut b);
+        assert_eq!(a.value(), 3);
+    }
+}
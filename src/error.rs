@@ -0,0 +1,54 @@
+use crate::runtime::{InboxError, InitError, MessageError};
+use std::{error, io};
+
+/// The errors returned by the runtime, so that callers can tell what failed
+/// instead of only getting a message from a boxed error.
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("protocol error: {0}")]
+    Protocol(#[from] ProtocolError),
+    #[error("invalid {var}: {reason}")]
+    Env { var: &'static str, reason: String },
+    #[error("state machine error: {0}")]
+    Handler(Box<dyn error::Error>),
+}
+
+/// The ways the messages a node reads can break Maelstrom's protocol.
+#[derive(thiserror::Error, Debug)]
+pub enum ProtocolError {
+    #[error(transparent)]
+    Message(#[from] MessageError),
+    #[error(transparent)]
+    Init(#[from] InitError),
+    #[error(transparent)]
+    Inbox(#[from] InboxError),
+}
+
+impl Error {
+    /// This wraps an error returned by a state machine.
+    pub fn handler(err: impl Into<Box<dyn error::Error>>) -> Self {
+        Self::Handler(err.into())
+    }
+}
+
+impl From<MessageError> for Error {
+    fn from(err: MessageError) -> Self {
+        Self::Protocol(err.into())
+    }
+}
+
+impl From<InitError> for Error {
+    fn from(err: InitError) -> Self {
+        Self::Protocol(err.into())
+    }
+}
+
+impl From<InboxError> for Error {
+    fn from(err: InboxError) -> Self {
+        Self::Protocol(err.into())
+    }
+}
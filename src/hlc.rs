@@ -0,0 +1,376 @@
+use crate::{
+    log,
+    log::Level,
+    protocol::{Message, Payload},
+    runtime::clock,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashSet,
+    env, fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The environment variable that enables HLC timestamps on inter-node messages when set to anything but `0`.
+pub const HLC_ENV: &str = "VORTEX_HLC";
+
+/// The field of a message body holding the sender's HLC timestamp.
+pub const HLC_FIELD: &str = "hlc";
+
+/// The furthest a peer's timestamp may be ahead of the local clock before it is not merged.
+pub const DEFAULT_MAX_DRIFT: Duration = Duration::from_secs(1);
+
+/// A hybrid logical clock timestamp.
+/// Timestamps are ordered by their physical component first and their logical component second.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Timestamp {
+    /// The physical component in milliseconds since the unix epoch.
+    pub physical: u64,
+    /// The logical component used to order events sharing the same physical component.
+    pub logical: u32,
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.physical, self.logical)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HlcError {
+    #[error("remote timestamp {remote} is {drift:?} ahead of the local clock")]
+    Drift { remote: Timestamp, drift: Duration },
+    #[error("logical component overflowed at {0}")]
+    Overflow(Timestamp),
+}
+
+/// A payload stamped with the sender's hybrid logical clock timestamp.
+/// The timestamp is flattened into the payload so it is sent as an extra `hlc` field.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Stamped<T> {
+    /// The timestamp of the send event.
+    pub hlc: Timestamp,
+    /// The payload being stamped.
+    #[serde(flatten)]
+    pub inner: T,
+}
+
+/// This represents a hybrid logical clock,
+/// which combines the physical clock with a logical counter
+/// so that timestamps respect causality without coordinating with a timestamp oracle.
+pub struct Hlc {
+    /// The latest timestamp issued or observed by the clock.
+    last: Timestamp,
+    /// The maximum amount a remote timestamp may be ahead of the local physical clock.
+    max_drift: Duration,
+    /// The source of physical time in milliseconds since the unix epoch.
+    clock: Box<dyn Fn() -> u64>,
+}
+
+impl Hlc {
+    /// This creates a clock driven by the system clock,
+    /// rejecting remote timestamps further than `max_drift` ahead of it.
+    pub fn new(max_drift: Duration) -> Self {
+        Self::with_clock(max_drift, system_clock)
+    }
+
+    /// This creates a clock driven by a custom physical clock in milliseconds.
+    pub fn with_clock(max_drift: Duration, clock: impl Fn() -> u64 + 'static) -> Self {
+        Self {
+            last: Timestamp::default(),
+            max_drift,
+            clock: Box::new(clock),
+        }
+    }
+
+    /// The latest timestamp issued or observed by the clock.
+    pub fn last(&self) -> Timestamp {
+        self.last
+    }
+
+    /// This issues a timestamp for a local or send event.
+    pub fn now(&mut self) -> Result<Timestamp, HlcError> {
+        let physical = (self.clock)();
+        self.last = if physical > self.last.physical {
+            Timestamp {
+                physical,
+                logical: 0,
+            }
+        } else {
+            self.tick(self.last)?
+        };
+        Ok(self.last)
+    }
+
+    /// This merges a timestamp received from a remote node into the clock,
+    /// returning the timestamp of the receive event.
+    pub fn update(&mut self, remote: Timestamp) -> Result<Timestamp, HlcError> {
+        let physical = (self.clock)();
+        let drift = Duration::from_millis(remote.physical.saturating_sub(physical));
+        if drift > self.max_drift {
+            return Err(HlcError::Drift { remote, drift });
+        }
+        let latest = self.last.max(remote);
+        self.last = if physical > latest.physical {
+            Timestamp {
+                physical,
+                logical: 0,
+            }
+        } else {
+            self.tick(latest)?
+        };
+        Ok(self.last)
+    }
+
+    /// This stamps an outbound payload with a fresh timestamp.
+    pub fn stamp<T>(&mut self, inner: T) -> Result<Stamped<T>, HlcError> {
+        let hlc = self.now()?;
+        Ok(Stamped { hlc, inner })
+    }
+
+    /// This merges the timestamp of an inbound payload into the clock,
+    /// returning the payload with the timestamp of the receive event.
+    pub fn observe<T>(&mut self, stamped: Stamped<T>) -> Result<Stamped<T>, HlcError> {
+        let hlc = self.update(stamped.hlc)?;
+        Ok(Stamped {
+            hlc,
+            inner: stamped.inner,
+        })
+    }
+
+    fn tick(&self, timestamp: Timestamp) -> Result<Timestamp, HlcError> {
+        let logical = timestamp
+            .logical
+            .checked_add(1)
+            .ok_or(HlcError::Overflow(timestamp))?;
+        Ok(Timestamp {
+            physical: timestamp.physical,
+            logical,
+        })
+    }
+}
+
+fn system_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// This stamps inter-node messages with the node's HLC timestamp in an `hlc` field of their body,
+/// and merges the timestamps of the messages received from peers into the node's clock,
+/// so that the timestamps of the cluster's messages respect causality.
+/// Only custom payloads are stamped, as the others are parsed without their extra fields.
+/// A timestamp further ahead than the maximum drift is logged and not merged,
+/// and in deterministic mode the physical clock stays at zero so that timestamps only count events.
+/// Messages to and from clients and services are left untouched.
+pub struct Stamper {
+    /// The ID of the node.
+    node_id: String,
+    /// The nodes in the cluster, which are the only ones whose messages are stamped.
+    peers: HashSet<String>,
+    /// The node's clock.
+    hlc: Hlc,
+    /// Whether messages are stamped at all.
+    enabled: bool,
+}
+
+impl Stamper {
+    /// This creates a stamper for the node's messages to and from its peers.
+    pub fn new(node_id: &str, peers: &[String]) -> Self {
+        let hlc = match clock::seed() {
+            Some(_) => Hlc::with_clock(DEFAULT_MAX_DRIFT, || 0),
+            None => Hlc::new(DEFAULT_MAX_DRIFT),
+        };
+        Self {
+            node_id: node_id.to_string(),
+            peers: peers.iter().cloned().collect(),
+            hlc,
+            enabled: true,
+        }
+    }
+
+    /// This creates a stamper that leaves every message untouched.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new("", &[])
+        }
+    }
+
+    /// This creates a stamper if `VORTEX_HLC` is set, and a disabled one otherwise.
+    pub fn from_env(node_id: &str, peers: &[String]) -> Self {
+        match env::var(HLC_ENV) {
+            Ok(v) if v != "0" => Self::new(node_id, peers),
+            _ => Self::disabled(),
+        }
+    }
+
+    /// Whether messages are stamped at all.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The latest timestamp issued or merged by the node's clock.
+    pub fn last(&self) -> Timestamp {
+        self.hlc.last()
+    }
+
+    /// Whether messages between the node and `peer` are stamped.
+    fn stamped(&self, peer: &str) -> bool {
+        self.enabled && peer != self.node_id && self.peers.contains(peer)
+    }
+
+    /// This converts the message to JSON, adding a fresh timestamp if it is to a peer.
+    pub fn stamp<T>(&mut self, message: &Message<T>) -> Result<Message<Value>, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        let mut value = serde_json::to_value(message)?;
+        let custom = matches!(message.body.payload, Payload::Custom(_));
+        if custom && self.stamped(&message.dest) {
+            match self.hlc.now() {
+                Ok(hlc) => {
+                    if let Some(body) = value["body"].as_object_mut() {
+                        body.insert(HLC_FIELD.to_string(), serde_json::to_value(hlc)?);
+                    }
+                }
+                Err(err) => log!(Level::Warn, "sending without a timestamp: {}", err),
+            }
+        }
+        serde_json::from_value(value)
+    }
+
+    /// This removes the timestamp of a message from a peer and merges it into the node's clock.
+    pub fn accept(&mut self, mut message: Message<Value>) -> Message<Value> {
+        if !self.stamped(&message.src) {
+            return message;
+        }
+        let Payload::Custom(Value::Object(body)) = &mut message.body.payload else {
+            return message;
+        };
+        let Some(remote) = body
+            .remove(HLC_FIELD)
+            .and_then(|hlc| serde_json::from_value(hlc).ok())
+        else {
+            return message;
+        };
+        if let Err(err) = self.hlc.update(remote) {
+            log!(
+                Level::Warn,
+                "not merging the timestamp from {}: {}",
+                message.src,
+                err
+            );
+        }
+        message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::{cell::Cell, rc::Rc};
+
+    /// This creates a clock driven by a physical clock that the test sets, starting at 100ms.
+    fn controlled(max_drift: Duration) -> (Hlc, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(100));
+        let clock = time.clone();
+        (Hlc::with_clock(max_drift, move || clock.get()), time)
+    }
+
+    fn ts(physical: u64, logical: u32) -> Timestamp {
+        Timestamp { physical, logical }
+    }
+
+    #[test]
+    fn now_keeps_increasing_when_the_clock_stalls_or_goes_back() {
+        let (mut hlc, time) = controlled(DEFAULT_MAX_DRIFT);
+        assert_eq!(hlc.now().unwrap(), ts(100, 0));
+        assert_eq!(hlc.now().unwrap(), ts(100, 1));
+        time.set(90);
+        assert_eq!(hlc.now().unwrap(), ts(100, 2));
+        time.set(101);
+        assert_eq!(hlc.now().unwrap(), ts(101, 0));
+    }
+
+    #[test]
+    fn update_moves_past_the_remote_and_local_timestamps() {
+        let (mut hlc, time) = controlled(DEFAULT_MAX_DRIFT);
+        assert_eq!(hlc.update(ts(105, 3)).unwrap(), ts(105, 4));
+        assert_eq!(hlc.now().unwrap(), ts(105, 5));
+        assert_eq!(hlc.update(ts(105, 2)).unwrap(), ts(105, 6));
+        assert_eq!(hlc.update(ts(105, 9)).unwrap(), ts(105, 10));
+        time.set(200);
+        assert_eq!(hlc.update(ts(150, 0)).unwrap(), ts(200, 0));
+    }
+
+    #[test]
+    fn update_rejects_a_timestamp_too_far_ahead() {
+        let (mut hlc, _) = controlled(Duration::from_millis(10));
+        assert_eq!(hlc.update(ts(110, 0)).unwrap(), ts(110, 1));
+        let err = hlc.update(ts(111, 0)).unwrap_err();
+        assert!(matches!(err, HlcError::Drift { drift, .. } if drift == Duration::from_millis(11)));
+        assert_eq!(hlc.last(), ts(110, 1));
+    }
+
+    #[test]
+    fn the_logical_component_does_not_wrap() {
+        let (mut hlc, time) = controlled(DEFAULT_MAX_DRIFT);
+        assert!(matches!(
+            hlc.update(ts(100, u32::MAX)),
+            Err(HlcError::Overflow(at)) if at == ts(100, u32::MAX)
+        ));
+        assert_eq!(
+            hlc.update(ts(100, u32::MAX - 1)).unwrap(),
+            ts(100, u32::MAX)
+        );
+        assert!(matches!(hlc.now(), Err(HlcError::Overflow(_))));
+        assert_eq!(hlc.last(), ts(100, u32::MAX));
+        time.set(101);
+        assert_eq!(hlc.now().unwrap(), ts(101, 0));
+    }
+
+    #[test]
+    fn stamped_payloads_carry_the_timestamp_as_a_field() {
+        let (mut hlc, _) = controlled(DEFAULT_MAX_DRIFT);
+        let stamped = hlc.stamp(json!({"type": "gossip"})).unwrap();
+        let value = serde_json::to_value(&stamped).unwrap();
+        assert_eq!(value["hlc"], json!({"physical": 100, "logical": 0}));
+        let (mut other, _) = controlled(DEFAULT_MAX_DRIFT);
+        let observed = other
+            .observe(serde_json::from_value::<Stamped<Value>>(value).unwrap())
+            .unwrap();
+        assert_eq!(observed.hlc, ts(100, 1));
+        assert_eq!(observed.inner["type"], "gossip");
+    }
+
+    fn body(message: &Message<Value>) -> &Value {
+        let Payload::Custom(body) = &message.body.payload else {
+            panic!("not a custom payload");
+        };
+        body
+    }
+
+    #[test]
+    fn the_stamper_only_stamps_custom_messages_between_peers() {
+        let peers = ["n1".to_string(), "n2".to_string()];
+        let mut n1 = Stamper::new("n1", &peers);
+        let mut n2 = Stamper::new("n2", &peers);
+        let gossip = Message::new("n1", "n2", json!({"type": "gossip"}));
+        let sent = n1.stamp(&gossip).unwrap();
+        assert_eq!(
+            body(&sent)[HLC_FIELD],
+            serde_json::to_value(n1.last()).unwrap()
+        );
+        let received = n2.accept(sent);
+        assert_eq!(body(&received), body(&gossip));
+        assert!(n2.last() > n1.last());
+        let to_client = Message::new("n1", "c1", json!({"type": "read_ok"}));
+        assert_eq!(body(&n1.stamp(&to_client).unwrap()), body(&to_client));
+    }
+}
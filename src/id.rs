@@ -0,0 +1,271 @@
+use crate::storage::{self, Json, SnapshotError};
+use serde::Serialize;
+use std::{
+    io,
+    path::Path,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// The start of flake ID timestamps in milliseconds since the unix epoch, i.e. 2024-01-01.
+const FLAKE_EPOCH: u64 = 1_704_067_200_000;
+
+/// The number of bits for the node number in a flake ID.
+const NODE_BITS: u32 = 10;
+
+/// The number of bits for the sequence number in a flake ID.
+const SEQUENCE_BITS: u32 = 12;
+
+/// This generates IDs that are unique across the cluster and across restarts.
+/// An ID combines the node ID, the generator's generation and a counter,
+/// where the generation is bumped and persisted every time the generator is resumed,
+/// so a restarted node never reissues the IDs of its previous run.
+#[derive(Clone, Debug, Serialize)]
+pub struct IdGenerator {
+    /// The ID of the node generating the IDs.
+    node_id: String,
+    /// The number of times the generator has been started before.
+    generation: u64,
+    /// The number of IDs generated in this generation.
+    counter: u64,
+}
+
+impl IdGenerator {
+    /// This creates a generator for the given generation.
+    /// Callers are responsible for never reusing a generation.
+    pub fn new(node_id: &str, generation: u64) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            generation,
+            counter: 0,
+        }
+    }
+
+    /// This creates a generator with the generation after the one persisted at `path`,
+    /// persisting the new generation before any ID is generated.
+    /// A missing file is treated as generation zero never having been used.
+    pub fn resume(node_id: &str, path: impl AsRef<Path>) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        let generation = match storage::load::<_, u64>(&Json, path) {
+            Ok(generation) => generation + 1,
+            Err(SnapshotError::Io(e)) if e.kind() == io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        storage::save(&Json, &generation, path)?;
+        Ok(Self::new(node_id, generation))
+    }
+
+    /// The generation of the generator.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// This generates the next ID.
+    pub fn next_id(&mut self) -> String {
+        self.counter += 1;
+        self.format(self.counter)
+    }
+
+    /// This generates the next `count` IDs,
+    /// reserving them as one contiguous block of the counter.
+    pub fn next_ids(&mut self, count: usize) -> Vec<String> {
+        let start = self.counter + 1;
+        self.counter += count as u64;
+        (start..=self.counter).map(|c| self.format(c)).collect()
+    }
+
+    fn format(&self, counter: u64) -> String {
+        format!("{}/{}/{}", self.node_id, self.generation, counter)
+    }
+}
+
+/// What a flake generator does when the clock reads earlier than the last timestamp it used,
+/// or when the sequence numbers of the current millisecond run out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkewPolicy {
+    /// Wait until the clock passes the last timestamp used.
+    Stall,
+    /// Keep issuing IDs from the last timestamp used, moving it ahead of the clock if needed.
+    Borrow,
+}
+
+/// The counters of clock skew events seen by a flake generator.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct SkewStats {
+    /// The number of times the clock read earlier than it did the time before.
+    pub backward_jumps: u64,
+    /// The number of times the generator waited for the clock.
+    pub stalls: u64,
+    /// The number of times the generator issued an ID ahead of the clock.
+    pub borrows: u64,
+}
+
+/// This generates 64-bit IDs ordered by time, made of a millisecond timestamp,
+/// a node number and a sequence number within the millisecond.
+/// IDs from one generator are strictly increasing even if the clock moves backwards.
+pub struct Flake {
+    /// The node number, which must be unique within the cluster.
+    node: u64,
+    /// The last timestamp used in milliseconds since the unix epoch,
+    /// which is ahead of the clock after borrowing.
+    last: u64,
+    /// The last reading of the clock, which tells the clock going backwards apart from borrowing.
+    observed: u64,
+    /// The last sequence number used within the last timestamp.
+    sequence: u64,
+    /// What to do when the clock is behind the last timestamp used.
+    policy: SkewPolicy,
+    /// The source of time in milliseconds since the unix epoch.
+    clock: Box<dyn Fn() -> u64>,
+    /// The clock skew events seen so far.
+    stats: SkewStats,
+}
+
+impl Flake {
+    /// This creates a generator for the node number driven by the system clock.
+    /// Only the lowest 10 bits of the node number are used.
+    pub fn new(node: u16, policy: SkewPolicy) -> Self {
+        Self::with_clock(node, policy, system_clock)
+    }
+
+    /// This creates a generator driven by a custom clock in milliseconds since the unix epoch.
+    pub fn with_clock(node: u16, policy: SkewPolicy, clock: impl Fn() -> u64 + 'static) -> Self {
+        Self {
+            node: u64::from(node) & ((1 << NODE_BITS) - 1),
+            last: 0,
+            observed: 0,
+            sequence: 0,
+            policy,
+            clock: Box::new(clock),
+            stats: SkewStats::default(),
+        }
+    }
+
+    /// This derives a node number from a Maelstrom node ID such as `n3`.
+    pub fn node_number(node_id: &str) -> Option<u16> {
+        node_id
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .parse()
+            .ok()
+    }
+
+    /// The clock skew events seen so far.
+    pub fn stats(&self) -> SkewStats {
+        self.stats
+    }
+
+    /// This generates the next ID.
+    pub fn next_id(&mut self) -> u64 {
+        let mut now = self.read_clock();
+        if now < self.last {
+            now = match self.policy {
+                SkewPolicy::Stall => {
+                    self.stats.stalls += 1;
+                    self.wait_until(self.last)
+                }
+                SkewPolicy::Borrow => {
+                    self.stats.borrows += 1;
+                    self.last
+                }
+            };
+        }
+        if now == self.last {
+            self.sequence += 1;
+            if self.sequence >= 1 << SEQUENCE_BITS {
+                now = match self.policy {
+                    SkewPolicy::Stall => {
+                        self.stats.stalls += 1;
+                        self.wait_until(self.last + 1)
+                    }
+                    SkewPolicy::Borrow => {
+                        self.stats.borrows += 1;
+                        self.last + 1
+                    }
+                };
+                self.sequence = 0;
+            }
+        } else {
+            self.sequence = 0;
+        }
+        self.last = now;
+        (now.saturating_sub(FLAKE_EPOCH) << (NODE_BITS + SEQUENCE_BITS))
+            | (self.node << SEQUENCE_BITS)
+            | self.sequence
+    }
+
+    /// This reads the clock, counting a backward jump if it reads earlier than the last reading.
+    fn read_clock(&mut self) -> u64 {
+        let now = (self.clock)();
+        if now < self.observed {
+            self.stats.backward_jumps += 1;
+        }
+        self.observed = now;
+        now
+    }
+
+    /// This waits until the clock reads at least `timestamp`, returning the clock's reading.
+    fn wait_until(&mut self, timestamp: u64) -> u64 {
+        loop {
+            let now = self.read_clock();
+            if now >= timestamp {
+                return now;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+fn system_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
+
+    /// This creates a generator driven by a clock that the test sets.
+    fn flake(policy: SkewPolicy) -> (Flake, Rc<Cell<u64>>) {
+        let time = Rc::new(Cell::new(FLAKE_EPOCH + 1000));
+        let clock = time.clone();
+        (Flake::with_clock(1, policy, move || clock.get()), time)
+    }
+
+    #[test]
+    fn borrowing_ahead_of_a_steady_clock_is_not_a_backward_jump() {
+        let (mut flake, _) = flake(SkewPolicy::Borrow);
+        let ids: Vec<u64> = (0..(1 << SEQUENCE_BITS) + 10)
+            .map(|_| flake.next_id())
+            .collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            flake.stats(),
+            SkewStats {
+                backward_jumps: 0,
+                stalls: 0,
+                borrows: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn a_clock_going_backwards_is_one_backward_jump() {
+        let (mut flake, time) = flake(SkewPolicy::Borrow);
+        let first = flake.next_id();
+        time.set(time.get() - 5);
+        let ids: Vec<u64> = (0..3).map(|_| flake.next_id()).collect();
+        assert!(first < ids[0] && ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(flake.stats().backward_jumps, 1);
+        assert_eq!(flake.stats().borrows, 3);
+    }
+
+    #[test]
+    fn node_numbers_come_from_node_ids() {
+        assert_eq!(Flake::node_number("n3"), Some(3));
+        assert_eq!(Flake::node_number("c12"), Some(12));
+        assert_eq!(Flake::node_number("n"), None);
+    }
+}
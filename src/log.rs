@@ -0,0 +1,114 @@
+use std::{
+    env, fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::OnceLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The environment variable that sets the most verbose level written to stderr,
+/// which is one of `off`, `error`, `warn`, `info`, `debug` or `trace`.
+pub const LOG_ENV: &str = "VORTEX_LOG";
+
+/// The level used when `VORTEX_LOG` is unset or not a level.
+pub const DEFAULT_LEVEL: Level = Level::Info;
+
+/// The level of a log line, from the most to the least severe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    /// Something failed, e.g. a message could not be handled.
+    Error,
+    /// Something went wrong that the node recovered from, e.g. a malformed message was dropped.
+    Warn,
+    /// Something worth knowing while running a test, e.g. a snapshot was written.
+    Info,
+    /// Every message received, applied and sent.
+    Debug,
+    /// The bodies of the messages as well.
+    Trace,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The most verbose level written, or `None` if logging is off, which is read from `VORTEX_LOG` once.
+static MAX_LEVEL: OnceLock<Option<Level>> = OnceLock::new();
+
+/// The ID of the node, which prefixes every line once the node is initialized.
+static NODE_ID: OnceLock<String> = OnceLock::new();
+
+/// The most verbose level written, or `None` if logging is off.
+pub fn max_level() -> Option<Level> {
+    *MAX_LEVEL.get_or_init(|| match env::var(LOG_ENV) {
+        Ok(level) if level.eq_ignore_ascii_case("off") => None,
+        Ok(level) => Some(level.parse().unwrap_or(DEFAULT_LEVEL)),
+        Err(_) => Some(DEFAULT_LEVEL),
+    })
+}
+
+/// Whether lines of the level are written.
+pub fn enabled(level: Level) -> bool {
+    max_level().is_some_and(|max| level <= max)
+}
+
+/// This sets the ID of the node that prefixes every line, which only the first call does.
+pub fn set_node_id(node_id: &str) {
+    let _ = NODE_ID.set(node_id.to_string());
+}
+
+/// This writes a line to stderr with the time in seconds since the Unix epoch, the level and the node's ID,
+/// e.g. `1700000000.123 WARN n1 dropping malformed message`, if lines of the level are written.
+/// Stdout is left to Maelstrom's protocol, which captures stderr as the node's log.
+pub fn write(level: Level, args: fmt::Arguments<'_>) {
+    if !enabled(level) {
+        return;
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let node_id = NODE_ID.get().map_or("-", String::as_str);
+    let _ = writeln!(
+        io::stderr().lock(),
+        "{}.{:03} {} {} {}",
+        now.as_secs(),
+        now.subsec_millis(),
+        level.name(),
+        node_id,
+        args
+    );
+}
+
+/// This writes a line to stderr at a level with `format!` arguments, e.g. `log!(Level::Info, "leader is {}", id)`,
+/// without formatting them unless lines of the level are written.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)+));
+        }
+    };
+}
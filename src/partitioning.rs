@@ -0,0 +1,110 @@
+use alloc::{format, string::String, vec::Vec};
+
+/// This is a trait for strategies that assign keys to the nodes that own them.
+/// Every node must build the same assignment from the same inputs,
+/// so that nodes agree on owners without coordinating.
+pub trait Partitioner {
+    /// The node owning the key.
+    /// This panics if there are no nodes to own the key.
+    fn owner(&self, key: &[u8]) -> &str;
+
+    /// The nodes holding replicas of the key in order of preference,
+    /// starting with the owner.
+    fn preference_list(&self, key: &[u8]) -> Vec<&str>;
+}
+
+/// This represents a consistent hashing ring
+/// where each node is placed on the ring at several points called virtual nodes.
+/// A key belongs to the first virtual node at or after the key's hash,
+/// and its replicas to the distinct nodes after that.
+#[derive(Clone, Debug)]
+pub struct HashRing {
+    /// The node IDs sorted so that the ring does not depend on the order they were given in.
+    nodes: Vec<String>,
+    /// The virtual nodes as pairs of their hash and the index of their node, sorted by hash.
+    ring: Vec<(u64, usize)>,
+    /// The number of nodes holding a copy of each key.
+    replication_factor: usize,
+}
+
+impl HashRing {
+    /// This builds a ring over the nodes with `vnodes` virtual nodes per node
+    /// and `replication_factor` copies of each key.
+    /// The replication factor is capped at the number of nodes.
+    pub fn new(node_ids: &[String], vnodes: usize, replication_factor: usize) -> Self {
+        let mut nodes = node_ids.to_vec();
+        nodes.sort();
+        nodes.dedup();
+        let mut ring = nodes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, node)| {
+                (0..vnodes.max(1)).map(move |v| (hash(format!("{}#{}", node, v).as_bytes()), i))
+            })
+            .collect::<Vec<_>>();
+        ring.sort();
+        let replication_factor = replication_factor.clamp(1, nodes.len().max(1));
+        Self {
+            nodes,
+            ring,
+            replication_factor,
+        }
+    }
+
+    /// The nodes on the ring.
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+
+    /// The number of nodes holding a copy of each key.
+    pub fn replication_factor(&self) -> usize {
+        self.replication_factor
+    }
+
+    /// The position on the ring of the first virtual node at or after the key's hash.
+    fn position(&self, key: &[u8]) -> usize {
+        let h = hash(key);
+        match self.ring.binary_search_by(|(vh, _)| vh.cmp(&h)) {
+            Ok(i) => i,
+            Err(i) if i == self.ring.len() => 0,
+            Err(i) => i,
+        }
+    }
+}
+
+impl Partitioner for HashRing {
+    fn owner(&self, key: &[u8]) -> &str {
+        let (_, node) = self.ring[self.position(key)];
+        &self.nodes[node]
+    }
+
+    fn preference_list(&self, key: &[u8]) -> Vec<&str> {
+        let start = self.position(key);
+        let mut owners: Vec<usize> = Vec::with_capacity(self.replication_factor);
+        for i in 0..self.ring.len() {
+            if owners.len() == self.replication_factor {
+                break;
+            }
+            let (_, node) = self.ring[(start + i) % self.ring.len()];
+            if !owners.contains(&node) {
+                owners.push(node);
+            }
+        }
+        owners.into_iter().map(|n| self.nodes[n].as_str()).collect()
+    }
+}
+
+/// This hashes bytes with FNV-1a followed by a finalizer to spread similar keys apart.
+/// Unlike the standard library's hasher the result is stable across builds and platforms.
+fn hash(bytes: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51afd7ed558ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+    h ^ (h >> 33)
+}
@@ -0,0 +1,8 @@
+pub use crate::{
+    protocol::{Message, Payload, Source, Workload},
+    runtime::{
+        Batcher, Context, Handlers, Inbox, MessageError, MessageType, Node, Priority, RetryPolicy,
+        Runtime, StateMachine, Timers, Tracer, TrafficLog,
+    },
+    services::Service,
+};
@@ -0,0 +1,493 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt, str::FromStr};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+pub use vortex_derive::Workload;
+
+/// The messages of Maelstrom's key-value services.
+pub mod kv;
+
+/// The RPC messages exchanged between Maelstrom's clients.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Message<T> {
+    /// The node the message comes from.
+    pub src: String,
+    /// The node this message is to.
+    pub dest: String,
+    /// The body of the message.
+    pub body: Body<T>,
+}
+
+/// The body of a message, with the fields that any type of message can have.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Body<T> {
+    /// The unique integer ID from the sender, which a request needs for its reply to refer to it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub msg_id: Option<usize>,
+    /// The msg_id of the request this is a reply to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to: Option<usize>,
+    /// The payload of the message, which holds its type and the rest of its fields.
+    #[serde(flatten)]
+    pub payload: Payload<T>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload<T> {
+    Init {
+        /// The ID of the node that receives this message.
+        node_id: String,
+        /// All nodes in the cluster including the node receiving the message.
+        node_ids: Vec<String>,
+    },
+    InitOk,
+    Topology {
+        /// The suggested neighbors of every node in the cluster.
+        topology: BTreeMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    Error {
+        /// The error code, 0-999 are reserved for Maelstrom, 1000+ are for custom error codes.
+        code: ErrorCode,
+        /// The optional message explaining the error.
+        text: Option<String>,
+    },
+    /// A message of Maelstrom's key-value services, which is tried before the custom payload.
+    #[cfg(feature = "kv")]
+    #[serde(untagged)]
+    Kv(kv::Kv),
+    #[serde(untagged)]
+    Custom(T),
+}
+
+impl<T> From<T> for Payload<T> {
+    fn from(body: T) -> Self {
+        Payload::Custom(body)
+    }
+}
+
+/// The error codes of Maelstrom's protocol, which are sent as integers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(from = "usize", into = "usize")]
+pub enum ErrorCode {
+    /// The request timed out, so it may or may not have happened.
+    Timeout,
+    /// The request was sent to a node that does not exist.
+    NodeNotFound,
+    /// The request is not supported by the node.
+    NotSupported,
+    /// The node cannot handle the request right now, e.g. because it has no leader.
+    TemporarilyUnavailable,
+    /// The request is malformed.
+    MalformedRequest,
+    /// The node crashed while handling the request, so it may or may not have happened.
+    Crash,
+    /// The request was aborted.
+    Abort,
+    /// The request read a key that does not exist.
+    KeyDoesNotExist,
+    /// The request created a key that already exists.
+    KeyAlreadyExists,
+    /// The request's precondition, such as a compare-and-set's expected value, did not hold.
+    PreconditionFailed,
+    /// The transaction conflicted with another one and was aborted.
+    TxnConflict,
+    /// Any other code, where 1000 and above are for custom error codes.
+    Custom(usize),
+}
+
+/// Whether a failed request may have happened anyway,
+/// which tells the caller whether it is safe to retry or whether it has to find out what happened first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailureKind {
+    /// The request definitely did not happen, e.g. a read of a key that does not exist.
+    Definite,
+    /// The request may or may not have happened, e.g. it timed out.
+    Indefinite,
+}
+
+impl ErrorCode {
+    /// Whether the request may have happened despite the error,
+    /// where timeouts and crashes are indefinite and every other error is definite.
+    /// Custom codes are assumed to be indefinite.
+    pub fn failure_kind(self) -> FailureKind {
+        match self {
+            Self::Timeout | Self::Crash | Self::Custom(_) => FailureKind::Indefinite,
+            _ => FailureKind::Definite,
+        }
+    }
+
+    /// Whether the error means the request definitely did not happen,
+    /// as opposed to timeouts and crashes after which it may have happened.
+    pub fn is_definite(self) -> bool {
+        self.failure_kind() == FailureKind::Definite
+    }
+}
+
+impl From<usize> for ErrorCode {
+    fn from(code: usize) -> Self {
+        match code {
+            0 => Self::Timeout,
+            1 => Self::NodeNotFound,
+            10 => Self::NotSupported,
+            11 => Self::TemporarilyUnavailable,
+            12 => Self::MalformedRequest,
+            13 => Self::Crash,
+            14 => Self::Abort,
+            20 => Self::KeyDoesNotExist,
+            21 => Self::KeyAlreadyExists,
+            22 => Self::PreconditionFailed,
+            30 => Self::TxnConflict,
+            code => Self::Custom(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for usize {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::Timeout => 0,
+            ErrorCode::NodeNotFound => 1,
+            ErrorCode::NotSupported => 10,
+            ErrorCode::TemporarilyUnavailable => 11,
+            ErrorCode::MalformedRequest => 12,
+            ErrorCode::Crash => 13,
+            ErrorCode::Abort => 14,
+            ErrorCode::KeyDoesNotExist => 20,
+            ErrorCode::KeyAlreadyExists => 21,
+            ErrorCode::PreconditionFailed => 22,
+            ErrorCode::TxnConflict => 30,
+            ErrorCode::Custom(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self, usize::from(*self))
+    }
+}
+
+/// The kinds of nodes a message can come from, told apart by the prefix of their IDs
+/// as Maelstrom names them, so that logic which treats clients and peers differently
+/// matches on the source instead of checking strings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Source<'a> {
+    /// A client of the workload, such as `c1`.
+    Client(&'a str),
+    /// A node of the cluster, such as `n1`.
+    Peer(&'a str),
+    /// One of Maelstrom's services, such as `lin-kv`.
+    Service(&'a str),
+}
+
+impl<'a> Source<'a> {
+    /// This tells the kind of node from its ID, where clients are `c` and nodes are `n`
+    /// followed by a number, and anything else is a service.
+    pub fn of(id: &'a str) -> Self {
+        let numbered = |prefix| {
+            id.strip_prefix(prefix)
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        };
+        if numbered('c') {
+            Source::Client(id)
+        } else if numbered('n') {
+            Source::Peer(id)
+        } else {
+            Source::Service(id)
+        }
+    }
+
+    /// The ID of the node.
+    pub fn id(self) -> &'a str {
+        match self {
+            Source::Client(id) | Source::Peer(id) | Source::Service(id) => id,
+        }
+    }
+}
+
+impl<T> Message<T> {
+    /// This creates a message without a msg_id or in_reply_to.
+    pub fn new(src: &str, dest: &str, payload: impl Into<Payload<T>>) -> Self {
+        Message {
+            src: src.to_string(),
+            dest: dest.to_string(),
+            body: Body {
+                msg_id: None,
+                in_reply_to: None,
+                payload: payload.into(),
+            },
+        }
+    }
+
+    /// This creates a reply to the message, which is sent back to its source
+    /// and refers to its msg_id.
+    pub fn reply(&self, payload: impl Into<Payload<T>>) -> Self {
+        let mut reply = Message::new(&self.dest, &self.src, payload);
+        reply.body.in_reply_to = self.body.msg_id;
+        reply
+    }
+
+    /// The kind of node the message comes from.
+    pub fn source(&self) -> Source<'_> {
+        Source::of(&self.src)
+    }
+
+    /// This creates an error reply to the message.
+    pub fn error_reply(&self, code: ErrorCode, text: impl Into<String>) -> Self {
+        self.reply(Payload::error(code, text))
+    }
+
+    /// This converts the custom payload of the message with a fallible function.
+    pub fn try_map<U, E>(self, f: impl FnOnce(T) -> Result<U, E>) -> Result<Message<U>, E> {
+        Ok(Message {
+            src: self.src,
+            dest: self.dest,
+            body: Body {
+                msg_id: self.body.msg_id,
+                in_reply_to: self.body.in_reply_to,
+                payload: self.body.payload.try_map(f)?,
+            },
+        })
+    }
+}
+
+impl<T> Payload<T> {
+    /// This creates an error payload with the code and the text explaining it.
+    pub fn error(code: ErrorCode, text: impl Into<String>) -> Self {
+        Payload::Error {
+            code,
+            text: Some(text.into()),
+        }
+    }
+
+    /// This converts the custom payload with a fallible function,
+    /// leaving the payloads defined by Maelstrom untouched.
+    pub fn try_map<U, E>(self, f: impl FnOnce(T) -> Result<U, E>) -> Result<Payload<U>, E> {
+        Ok(match self {
+            Payload::Init { node_id, node_ids } => Payload::Init { node_id, node_ids },
+            Payload::InitOk => Payload::InitOk,
+            Payload::Topology { topology } => Payload::Topology { topology },
+            Payload::TopologyOk => Payload::TopologyOk,
+            Payload::Error { code, text } => Payload::Error { code, text },
+            #[cfg(feature = "kv")]
+            Payload::Kv(kv) => Payload::Kv(kv),
+            Payload::Custom(body) => Payload::Custom(f(body)?),
+        })
+    }
+}
+
+/// This is a trait for the custom payloads of a workload, made of requests and the `Ok` replies to them,
+/// which is derived with `#[derive(Workload)]` by pairing every `X` variant with an `XOk` variant.
+pub trait Workload {
+    /// The `type` of the message.
+    fn kind(&self) -> &'static str;
+
+    /// The `type` of the reply to the message, if it is a request that is replied to.
+    fn reply_kind(&self) -> Option<&'static str>;
+
+    /// Whether the message is the reply to a request.
+    fn is_reply(&self) -> bool;
+}
+
+impl<T> Message<T>
+where
+    T: Workload,
+{
+    /// Whether the message is a request that is replied to, e.g. for `StateMachine::expects_reply`.
+    pub fn is_request(&self) -> bool {
+        self.body.in_reply_to.is_none()
+            && matches!(&self.body.payload, Payload::Custom(body) if body.reply_kind().is_some())
+    }
+}
+
+impl<T> FromStr for Message<T>
+where
+    T: DeserializeOwned,
+{
+    type Err = serde_json::Error;
+
+    /// This is used to deserialize a message from a string.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// A workload's payload, as in the echo example of Maelstrom's protocol doc.
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    enum Echo {
+        Echo { echo: String },
+        EchoOk { echo: String },
+    }
+
+    /// This parses a message from its JSON and checks that it serializes back to exactly the same JSON.
+    fn round_trip<T>(json: Value) -> Message<T>
+    where
+        T: Serialize + DeserializeOwned,
+    {
+        let message: Message<T> = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&message).unwrap(), json);
+        message
+    }
+
+    #[test]
+    fn init() {
+        let message = round_trip::<Value>(json!({
+            "src": "c1",
+            "dest": "n3",
+            "body": {"type": "init", "msg_id": 1, "node_id": "n3", "node_ids": ["n1", "n2", "n3"]},
+        }));
+        assert!(matches!(
+            message.body.payload,
+            Payload::Init { node_id, node_ids } if node_id == "n3" && node_ids.len() == 3
+        ));
+        let reply = round_trip::<Value>(json!({
+            "src": "n3",
+            "dest": "c1",
+            "body": {"type": "init_ok", "in_reply_to": 1},
+        }));
+        assert!(matches!(reply.body.payload, Payload::InitOk));
+        assert_eq!(reply.body.in_reply_to, Some(1));
+    }
+
+    #[test]
+    fn topology() {
+        let message = round_trip::<Value>(json!({
+            "src": "c1",
+            "dest": "n1",
+            "body": {"type": "topology", "msg_id": 2, "topology": {"n1": ["n2", "n3"], "n2": ["n1"], "n3": ["n1"]}},
+        }));
+        assert!(matches!(
+            message.body.payload,
+            Payload::Topology { topology } if topology["n1"] == ["n2", "n3"]
+        ));
+    }
+
+    #[test]
+    fn error() {
+        let message = round_trip::<Value>(json!({
+            "src": "n1",
+            "dest": "c1",
+            "body": {
+                "type": "error",
+                "in_reply_to": 5,
+                "code": 11,
+                "text": "Node n5 is waiting for quorum and cannot service requests yet",
+            },
+        }));
+        assert!(matches!(
+            message.body.payload,
+            Payload::Error {
+                code: ErrorCode::TemporarilyUnavailable,
+                text: Some(_),
+            }
+        ));
+    }
+
+    #[test]
+    fn error_codes() {
+        let codes = [
+            (0, ErrorCode::Timeout, FailureKind::Indefinite),
+            (1, ErrorCode::NodeNotFound, FailureKind::Definite),
+            (10, ErrorCode::NotSupported, FailureKind::Definite),
+            (11, ErrorCode::TemporarilyUnavailable, FailureKind::Definite),
+            (12, ErrorCode::MalformedRequest, FailureKind::Definite),
+            (13, ErrorCode::Crash, FailureKind::Indefinite),
+            (14, ErrorCode::Abort, FailureKind::Definite),
+            (20, ErrorCode::KeyDoesNotExist, FailureKind::Definite),
+            (21, ErrorCode::KeyAlreadyExists, FailureKind::Definite),
+            (22, ErrorCode::PreconditionFailed, FailureKind::Definite),
+            (30, ErrorCode::TxnConflict, FailureKind::Definite),
+            (1000, ErrorCode::Custom(1000), FailureKind::Indefinite),
+        ];
+        for (number, code, kind) in codes {
+            let parsed: ErrorCode = serde_json::from_value(json!(number)).unwrap();
+            assert_eq!(parsed, code);
+            assert_eq!(serde_json::to_value(code).unwrap(), json!(number));
+            assert_eq!(code.failure_kind(), kind);
+        }
+    }
+
+    #[test]
+    fn error_is_not_swallowed_by_custom() {
+        let json = json!({
+            "src": "n1",
+            "dest": "c1",
+            "body": {"type": "error", "in_reply_to": 5, "text": "no code"},
+        });
+        assert!(serde_json::from_value::<Message<Echo>>(json).is_err());
+    }
+
+    #[test]
+    fn kv() {
+        let examples = [
+            json!({"type": "read", "key": "foo"}),
+            json!({"type": "read_ok", "value": "bar"}),
+            json!({"type": "write", "key": "foo", "value": 2}),
+            json!({"type": "write_ok"}),
+            json!({"type": "cas", "key": "foo", "from": 1, "to": 2, "create_if_not_exists": true}),
+            json!({"type": "cas_ok"}),
+        ];
+        for example in examples {
+            let op: kv::Kv = serde_json::from_value(example.clone()).unwrap();
+            assert_eq!(serde_json::to_value(&op).unwrap(), example);
+        }
+        let cas: kv::Kv =
+            serde_json::from_value(json!({"type": "cas", "key": "foo", "from": 1, "to": 2}))
+                .unwrap();
+        assert!(matches!(
+            cas,
+            kv::Kv::Cas {
+                create_if_not_exists: false,
+                ..
+            }
+        ));
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn kv_payload() {
+        let message = round_trip::<Value>(json!({
+            "src": "n1",
+            "dest": "lin-kv",
+            "body": {"type": "read", "msg_id": 3, "key": "foo"},
+        }));
+        assert!(matches!(
+            message.body.payload,
+            Payload::Kv(kv::Kv::Read { .. })
+        ));
+    }
+
+    #[test]
+    fn workload() {
+        let message = round_trip::<Echo>(json!({
+            "src": "c1",
+            "dest": "n1",
+            "body": {"type": "echo", "msg_id": 1, "echo": "Please echo 35"},
+        }));
+        assert!(matches!(
+            message.body.payload,
+            Payload::Custom(Echo::Echo { .. })
+        ));
+        let reply = round_trip::<Echo>(json!({
+            "src": "n1",
+            "dest": "c1",
+            "body": {"type": "echo_ok", "msg_id": 1, "in_reply_to": 1, "echo": "Please echo 35"},
+        }));
+        assert!(matches!(
+            reply.body.payload,
+            Payload::Custom(Echo::EchoOk { .. })
+        ));
+    }
+}
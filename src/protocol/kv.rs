@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The messages of Maelstrom's key-value services.
+/// Keys and values can be any JSON value.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Kv {
+    Read {
+        key: Value,
+    },
+    ReadOk {
+        value: Value,
+    },
+    Write {
+        key: Value,
+        value: Value,
+    },
+    WriteOk,
+    Cas {
+        key: Value,
+        from: Value,
+        to: Value,
+        /// Whether the key should be created with `to` if it does not exist.
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    CasOk,
+}
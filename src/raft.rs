@@ -0,0 +1,678 @@
+use crate::runtime::{Priority, Rng};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+/// The shortest time a follower waits without hearing from a leader before starting an election.
+/// Each election waits a random time between this and twice this, so that elections rarely collide.
+pub const ELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The time between the leader's heartbeats, which must be well below the election timeout.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The maximum number of entries sent to a follower in one append.
+const APPEND_LIMIT: usize = 100;
+
+/// This is a trait for the state machines that Raft replicates,
+/// which every node applies the same commands to in the same order once they are committed.
+pub trait Replicated {
+    /// The commands that are replicated through the log.
+    type Command: Clone;
+    /// The result of applying a command.
+    type Output;
+
+    /// This applies a committed command to the state machine.
+    fn apply(&mut self, command: Self::Command) -> Self::Output;
+}
+
+/// An entry of the log, where a `None` command is the no-op a leader appends when it is elected
+/// so that it can commit the entries of earlier terms.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry<C> {
+    pub term: u64,
+    pub command: Option<C>,
+}
+
+/// The state that a node must persist before responding to any message,
+/// so that it neither votes twice in a term nor forgets entries it acknowledged after a restart.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HardState<C> {
+    /// The latest term the node has seen.
+    pub term: u64,
+    /// The candidate the node voted for in the term, if any.
+    pub voted_for: Option<String>,
+    /// The log, where the entry at index `i` has Raft's index `i + 1`.
+    pub log: Vec<Entry<C>>,
+    /// The highest index known to be committed,
+    /// so that a restarted node can tell which entries it had already applied.
+    #[serde(default)]
+    pub commit_index: u64,
+}
+
+impl<C> Default for HardState<C> {
+    fn default() -> Self {
+        Self {
+            term: 0,
+            voted_for: None,
+            log: Vec::new(),
+            commit_index: 0,
+        }
+    }
+}
+
+/// The messages of Raft exchanged between nodes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum RaftMessage<C> {
+    RequestVote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    RequestVoteOk {
+        term: u64,
+        vote_granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<C>>,
+        leader_commit: u64,
+    },
+    AppendEntriesOk {
+        term: u64,
+        success: bool,
+        /// The index up to which the follower's log matches the leader's on success,
+        /// or the index the leader should retry from after on failure.
+        match_index: u64,
+    },
+}
+
+impl<C> RaftMessage<C> {
+    /// The priority of the message, which is control for everything but appends carrying entries,
+    /// so that votes and heartbeats are not delayed by the replication of the log.
+    pub fn priority(&self) -> Priority {
+        match self {
+            RaftMessage::AppendEntries { entries, .. } if !entries.is_empty() => Priority::Normal,
+            _ => Priority::Control,
+        }
+    }
+}
+
+/// The role of a node in its current term.
+#[derive(Debug, Serialize)]
+enum Role {
+    Follower,
+    Candidate {
+        votes: HashSet<String>,
+    },
+    Leader {
+        /// The index of the next entry to send to each follower.
+        next_index: HashMap<String, u64>,
+        /// The highest index known to be replicated on each follower.
+        match_index: HashMap<String, u64>,
+    },
+}
+
+/// The error returned when a command is proposed to a node that is not the leader.
+#[derive(thiserror::Error, Debug)]
+#[error("node is not the leader, which is {leader:?}")]
+pub struct NotLeader {
+    /// The leader of the node's current term, if known.
+    pub leader: Option<String>,
+}
+
+/// This represents a node of a Raft cluster replicating a state machine:
+/// it elects a leader, replicates the commands proposed to the leader to the other nodes' logs,
+/// and applies the commands to the state machine once a majority has them.
+/// This does no I/O itself: the messages to send are returned from `handle` and `poll`,
+/// and the hard state should be persisted whenever `take_changed` returns true.
+#[derive(Serialize)]
+pub struct Raft<M: Replicated> {
+    id: String,
+    /// The other nodes in the cluster.
+    peers: Vec<String>,
+    #[serde(skip)]
+    hard: HardState<M::Command>,
+    /// The highest index known to be committed.
+    commit_index: u64,
+    /// The highest index applied to the state machine.
+    last_applied: u64,
+    role: Role,
+    /// The leader of the current term, if known.
+    leader: Option<String>,
+    machine: M,
+    #[serde(skip)]
+    election_deadline: Instant,
+    #[serde(skip)]
+    heartbeat_deadline: Instant,
+    /// The generator for randomized election timeouts.
+    #[serde(skip)]
+    rng: Rng,
+    /// Whether the hard state changed since `take_changed` was last called.
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl<M: Replicated> Raft<M> {
+    /// This creates a follower in a cluster of the given nodes with an empty log.
+    pub fn new(id: &str, node_ids: &[String], machine: M, now: Instant) -> Self {
+        let mut raft = Self {
+            id: id.to_string(),
+            peers: node_ids.iter().filter(|n| *n != id).cloned().collect(),
+            hard: HardState::default(),
+            commit_index: 0,
+            last_applied: 0,
+            role: Role::Follower,
+            leader: None,
+            machine,
+            election_deadline: now,
+            heartbeat_deadline: now,
+            rng: Rng::for_node(id),
+            changed: false,
+        };
+        raft.reset_election(now);
+        raft
+    }
+
+    /// This restores the hard state persisted before a restart.
+    /// The state machine is not persisted, so the next `apply_committed` applies the committed entries again.
+    pub fn restore(mut self, hard: HardState<M::Command>) -> Self {
+        self.commit_index = hard.commit_index;
+        self.hard = hard;
+        self
+    }
+
+    /// The state that must be persisted.
+    pub fn hard_state(&self) -> &HardState<M::Command> {
+        &self.hard
+    }
+
+    /// Whether the hard state changed since this was last called, meaning it should be persisted.
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    /// The current term.
+    pub fn term(&self) -> u64 {
+        self.hard.term
+    }
+
+    /// Whether the node is the leader of its current term.
+    pub fn is_leader(&self) -> bool {
+        matches!(self.role, Role::Leader { .. })
+    }
+
+    /// The leader of the current term, if known.
+    pub fn leader(&self) -> Option<&str> {
+        self.leader.as_deref()
+    }
+
+    /// The highest index known to be committed.
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    /// The replicated state machine.
+    pub fn machine(&self) -> &M {
+        &self.machine
+    }
+
+    /// The index of the last entry in the log.
+    fn last_index(&self) -> u64 {
+        self.hard.log.len() as u64
+    }
+
+    /// The term of the entry at the index, where index 0 is before the first entry.
+    fn term_at(&self, index: u64) -> u64 {
+        match index {
+            0 => 0,
+            index => self
+                .hard
+                .log
+                .get(index as usize - 1)
+                .map_or(0, |entry| entry.term),
+        }
+    }
+
+    /// The number of nodes that make up a majority of the cluster.
+    fn quorum(&self) -> usize {
+        let nodes = self.peers.len() + 1;
+        nodes / 2 + 1
+    }
+
+    /// This picks a new random deadline for an election.
+    fn reset_election(&mut self, now: Instant) {
+        let jitter = self.rng.below(ELECTION_TIMEOUT.as_millis() as u64);
+        self.election_deadline = now + ELECTION_TIMEOUT + Duration::from_millis(jitter);
+    }
+
+    /// This moves the node to a newer term as a follower.
+    fn step_down(&mut self, term: u64, now: Instant) {
+        if term > self.hard.term {
+            self.hard.term = term;
+            self.hard.voted_for = None;
+            self.leader = None;
+            self.changed = true;
+        }
+        if !matches!(self.role, Role::Follower) {
+            self.role = Role::Follower;
+            self.reset_election(now);
+        }
+    }
+
+    /// This appends a command to the leader's log, returning its index,
+    /// which is replicated to the followers by the next `poll`.
+    pub fn propose(&mut self, command: M::Command) -> Result<u64, NotLeader> {
+        if !self.is_leader() {
+            return Err(NotLeader {
+                leader: self.leader.clone(),
+            });
+        }
+        self.hard.log.push(Entry {
+            term: self.hard.term,
+            command: Some(command),
+        });
+        self.changed = true;
+        if self.peers.is_empty() {
+            self.commit(self.last_index());
+        }
+        Ok(self.last_index())
+    }
+
+    /// This returns the messages that are due by `now`:
+    /// the vote requests of a new election if no leader was heard from in time,
+    /// and the leader's appends of new entries to its followers, or heartbeats if they are due.
+    pub fn poll(&mut self, now: Instant) -> Vec<(String, RaftMessage<M::Command>)> {
+        let mut messages = Vec::new();
+        if !self.is_leader() && now >= self.election_deadline {
+            self.start_election(now, &mut messages);
+        }
+        if let Role::Leader { next_index, .. } = &self.role {
+            let heartbeat = now >= self.heartbeat_deadline;
+            let due: Vec<String> = self
+                .peers
+                .iter()
+                .filter(|peer| heartbeat || next_index[*peer] <= self.last_index())
+                .cloned()
+                .collect();
+            for peer in due {
+                messages.push(self.append_to(&peer));
+            }
+            if heartbeat {
+                self.heartbeat_deadline = now + HEARTBEAT_INTERVAL;
+            }
+        }
+        messages
+    }
+
+    /// This starts an election for the next term, voting for the node itself.
+    fn start_election(
+        &mut self,
+        now: Instant,
+        messages: &mut Vec<(String, RaftMessage<M::Command>)>,
+    ) {
+        self.hard.term += 1;
+        self.hard.voted_for = Some(self.id.clone());
+        self.changed = true;
+        self.leader = None;
+        self.role = Role::Candidate {
+            votes: HashSet::from([self.id.clone()]),
+        };
+        self.reset_election(now);
+        if self.quorum() == 1 {
+            self.become_leader(now);
+            return;
+        }
+        for peer in &self.peers {
+            messages.push((
+                peer.clone(),
+                RaftMessage::RequestVote {
+                    term: self.hard.term,
+                    last_log_index: self.last_index(),
+                    last_log_term: self.term_at(self.last_index()),
+                },
+            ));
+        }
+    }
+
+    /// This makes the candidate the leader, appending a no-op so that entries of earlier terms get committed.
+    fn become_leader(&mut self, now: Instant) {
+        self.hard.log.push(Entry {
+            term: self.hard.term,
+            command: None,
+        });
+        self.changed = true;
+        let next = self.last_index();
+        self.role = Role::Leader {
+            next_index: self.peers.iter().map(|p| (p.clone(), next)).collect(),
+            match_index: self.peers.iter().map(|p| (p.clone(), 0)).collect(),
+        };
+        self.leader = Some(self.id.clone());
+        self.heartbeat_deadline = now;
+        if self.peers.is_empty() {
+            self.commit(self.last_index());
+        }
+    }
+
+    /// This creates an append of the entries a follower is missing, up to `APPEND_LIMIT` of them,
+    /// and assumes they will be accepted so that the next append continues after them.
+    fn append_to(&mut self, peer: &str) -> (String, RaftMessage<M::Command>) {
+        let last_index = self.last_index();
+        let Role::Leader { next_index, .. } = &mut self.role else {
+            unreachable!("only leaders append entries");
+        };
+        let next = next_index.get(peer).copied().unwrap_or(last_index + 1);
+        let prev_log_index = next - 1;
+        let end = (prev_log_index as usize + APPEND_LIMIT).min(last_index as usize);
+        let entries = self.hard.log[prev_log_index as usize..end].to_vec();
+        next_index.insert(peer.to_string(), end as u64 + 1);
+        (
+            peer.to_string(),
+            RaftMessage::AppendEntries {
+                term: self.hard.term,
+                prev_log_index,
+                prev_log_term: self.term_at(prev_log_index),
+                entries,
+                leader_commit: self.commit_index,
+            },
+        )
+    }
+
+    /// This handles a message from another node, returning the messages to send in response.
+    pub fn handle(
+        &mut self,
+        src: &str,
+        message: RaftMessage<M::Command>,
+        now: Instant,
+    ) -> Vec<(String, RaftMessage<M::Command>)> {
+        let term = match &message {
+            RaftMessage::RequestVote { term, .. }
+            | RaftMessage::RequestVoteOk { term, .. }
+            | RaftMessage::AppendEntries { term, .. }
+            | RaftMessage::AppendEntriesOk { term, .. } => *term,
+        };
+        if term > self.hard.term {
+            self.step_down(term, now);
+        }
+        let mut messages = Vec::new();
+        match message {
+            RaftMessage::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => {
+                let up_to_date = (last_log_term, last_log_index)
+                    >= (self.term_at(self.last_index()), self.last_index());
+                let vote_granted = term == self.hard.term
+                    && up_to_date
+                    && self.hard.voted_for.as_deref().is_none_or(|v| v == src);
+                if vote_granted {
+                    self.hard.voted_for = Some(src.to_string());
+                    self.changed = true;
+                    self.reset_election(now);
+                }
+                messages.push((
+                    src.to_string(),
+                    RaftMessage::RequestVoteOk {
+                        term: self.hard.term,
+                        vote_granted,
+                    },
+                ));
+            }
+            RaftMessage::RequestVoteOk { term, vote_granted } => {
+                if let Role::Candidate { votes } = &mut self.role {
+                    if term == self.hard.term && vote_granted {
+                        votes.insert(src.to_string());
+                        if votes.len() >= self.quorum() {
+                            self.become_leader(now);
+                            messages.extend(self.poll(now));
+                        }
+                    }
+                }
+            }
+            RaftMessage::AppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                let reply = self.append(
+                    src,
+                    term,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                    now,
+                );
+                messages.push((src.to_string(), reply));
+            }
+            RaftMessage::AppendEntriesOk {
+                term,
+                success,
+                match_index,
+            } => {
+                if term != self.hard.term {
+                    return messages;
+                }
+                let Role::Leader {
+                    next_index,
+                    match_index: matched,
+                } = &mut self.role
+                else {
+                    return messages;
+                };
+                if success {
+                    let matched = matched.entry(src.to_string()).or_default();
+                    *matched = (*matched).max(match_index);
+                    let next = next_index.entry(src.to_string()).or_default();
+                    *next = (*next).max(match_index + 1);
+                    self.advance_commit();
+                } else {
+                    next_index.insert(src.to_string(), match_index + 1);
+                    messages.push(self.append_to(src));
+                }
+            }
+        }
+        messages
+    }
+
+    /// This handles an append from the leader of a term, returning the reply.
+    #[allow(clippy::too_many_arguments)]
+    fn append(
+        &mut self,
+        src: &str,
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<Entry<M::Command>>,
+        leader_commit: u64,
+        now: Instant,
+    ) -> RaftMessage<M::Command> {
+        if term < self.hard.term {
+            return RaftMessage::AppendEntriesOk {
+                term: self.hard.term,
+                success: false,
+                match_index: self.commit_index,
+            };
+        }
+        self.step_down(term, now);
+        self.leader = Some(src.to_string());
+        self.reset_election(now);
+        if prev_log_index > self.last_index() || self.term_at(prev_log_index) != prev_log_term {
+            // Committed entries always match the leader's, so the leader can resume from there.
+            return RaftMessage::AppendEntriesOk {
+                term: self.hard.term,
+                success: false,
+                match_index: self.commit_index.min(self.last_index()),
+            };
+        }
+        let match_index = prev_log_index + entries.len() as u64;
+        for (index, entry) in (prev_log_index + 1..).zip(entries) {
+            if index <= self.last_index() {
+                if self.term_at(index) == entry.term {
+                    continue;
+                }
+                self.hard.log.truncate(index as usize - 1);
+            }
+            self.hard.log.push(entry);
+            self.changed = true;
+        }
+        // A delayed append may match less of the log than is already known to be committed.
+        self.commit(self.commit_index.max(leader_commit.min(match_index)));
+        RaftMessage::AppendEntriesOk {
+            term: self.hard.term,
+            success: true,
+            match_index,
+        }
+    }
+
+    /// This moves the leader's commit index to the highest entry of its term that a majority has.
+    fn advance_commit(&mut self) {
+        let Role::Leader { match_index, .. } = &self.role else {
+            return;
+        };
+        let mut matched: Vec<u64> = match_index.values().copied().collect();
+        matched.push(self.last_index());
+        matched.sort_unstable_by(|a, b| b.cmp(a));
+        let majority = matched[self.quorum() - 1];
+        if self.term_at(majority) == self.hard.term {
+            self.commit(majority);
+        }
+    }
+
+    /// This moves the commit index up to the index, which is persisted with the hard state.
+    fn commit(&mut self, index: u64) {
+        if index > self.commit_index {
+            self.commit_index = index;
+            self.hard.commit_index = index;
+            self.changed = true;
+        }
+    }
+
+    /// This applies the committed entries that have not been applied yet to the state machine,
+    /// returning the outputs of their commands along with their indexes.
+    pub fn apply_committed(&mut self) -> Vec<(u64, M::Output)> {
+        let mut outputs = Vec::new();
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let entry = &self.hard.log[self.last_applied as usize - 1];
+            if let Some(command) = entry.command.clone() {
+                outputs.push((self.last_applied, self.machine.apply(command)));
+            }
+        }
+        outputs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::clock;
+    use std::collections::VecDeque;
+
+    /// A state machine that records the commands applied to it.
+    #[derive(Default)]
+    struct Log(Vec<u64>);
+
+    impl Replicated for Log {
+        type Command = u64;
+        type Output = u64;
+
+        fn apply(&mut self, command: u64) -> u64 {
+            self.0.push(command);
+            command
+        }
+    }
+
+    fn nodes() -> Vec<String> {
+        vec!["n1".to_string(), "n2".to_string(), "n3".to_string()]
+    }
+
+    /// An append from n1 in term 1 of the entries after `prev`, whose commands are their indexes.
+    fn append(prev: u64, last: u64, leader_commit: u64) -> RaftMessage<u64> {
+        RaftMessage::AppendEntries {
+            term: 1,
+            prev_log_index: prev,
+            prev_log_term: if prev == 0 { 0 } else { 1 },
+            entries: (prev + 1..=last)
+                .map(|index| Entry {
+                    term: 1,
+                    command: Some(index),
+                })
+                .collect(),
+            leader_commit,
+        }
+    }
+
+    /// This delivers the messages sent by n1 and everything sent in response until none are left.
+    fn deliver(
+        cluster: &mut HashMap<String, Raft<Log>>,
+        messages: Vec<(String, RaftMessage<u64>)>,
+        now: Instant,
+    ) {
+        let mut queue: VecDeque<_> = messages
+            .into_iter()
+            .map(|(dest, message)| ("n1".to_string(), dest, message))
+            .collect();
+        while let Some((src, dest, message)) = queue.pop_front() {
+            let node = cluster.get_mut(&dest).unwrap();
+            for (next, message) in node.handle(&src, message, now) {
+                queue.push_back((dest.clone(), next, message));
+            }
+        }
+    }
+
+    #[test]
+    fn a_delayed_append_does_not_move_the_commit_index_back() {
+        let now = clock::now();
+        let mut n2 = Raft::new("n2", &nodes(), Log::default(), now);
+        n2.handle("n1", append(0, 3, 2), now);
+        assert_eq!(n2.commit_index(), 2);
+        n2.handle("n1", append(0, 1, 3), now);
+        assert_eq!(n2.commit_index(), 2);
+        assert_eq!(n2.apply_committed(), vec![(1, 1), (2, 2)]);
+    }
+
+    #[test]
+    fn a_restored_node_applies_its_committed_entries_again() {
+        let now = clock::now();
+        let mut n2 = Raft::new("n2", &nodes(), Log::default(), now);
+        n2.handle("n1", append(0, 3, 2), now);
+        n2.apply_committed();
+        assert!(n2.take_changed());
+        let mut restarted =
+            Raft::new("n2", &nodes(), Log::default(), now).restore(n2.hard_state().clone());
+        assert_eq!(restarted.commit_index(), 2);
+        assert_eq!(restarted.apply_committed(), vec![(1, 1), (2, 2)]);
+        assert_eq!(restarted.machine().0, n2.machine().0);
+    }
+
+    #[test]
+    fn a_leader_commits_once_a_majority_has_an_entry() {
+        let now = clock::now();
+        let mut cluster: HashMap<String, Raft<Log>> = nodes()
+            .iter()
+            .map(|id| (id.clone(), Raft::new(id, &nodes(), Log::default(), now)))
+            .collect();
+        let later = now + ELECTION_TIMEOUT * 2;
+        let votes = cluster.get_mut("n1").unwrap().poll(later);
+        deliver(&mut cluster, votes, later);
+        let n1 = cluster.get_mut("n1").unwrap();
+        assert!(n1.is_leader());
+        assert_eq!(n1.propose(7).unwrap(), 2);
+        let appends = n1.poll(later);
+        deliver(&mut cluster, appends, later);
+        let n1 = cluster.get_mut("n1").unwrap();
+        assert_eq!(n1.commit_index(), 2);
+        assert_eq!(n1.apply_committed(), vec![(2, 7)]);
+        assert_eq!(cluster["n2"].leader(), Some("n1"));
+        assert!(cluster.get_mut("n3").unwrap().propose(8).is_err());
+    }
+}
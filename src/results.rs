@@ -0,0 +1,259 @@
+use std::{fs, io, path::Path};
+
+mod edn;
+
+pub use edn::{Edn, EdnError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ResultsError {
+    #[error("results io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("results are not valid edn: {0}")]
+    Edn(#[from] EdnError),
+    #[error("results have no {0} field")]
+    Missing(&'static str),
+}
+
+/// This represents Jepsen's verdict for a checker, which is `:unknown` when it could not decide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Validity {
+    Valid,
+    Invalid,
+    Unknown,
+}
+
+impl Validity {
+    fn from_edn(value: Option<&Edn>) -> Self {
+        match value {
+            Some(Edn::Bool(true)) => Self::Valid,
+            Some(Edn::Bool(false)) => Self::Invalid,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// The counts of the operations clients performed, by their outcome.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OpStats {
+    pub count: i64,
+    pub ok_count: i64,
+    pub fail_count: i64,
+    pub info_count: i64,
+}
+
+/// The messages exchanged by one group of nodes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct NetStats {
+    pub send_count: i64,
+    pub recv_count: i64,
+    pub msg_count: i64,
+    /// The number of messages per client operation, which is what the challenges set targets on.
+    pub msgs_per_op: f64,
+}
+
+impl NetStats {
+    fn from_edn(value: Option<&Edn>) -> Self {
+        let field = |name| value.and_then(|v| v.get(name));
+        Self {
+            send_count: field("send-count").and_then(Edn::as_i64).unwrap_or(0),
+            recv_count: field("recv-count").and_then(Edn::as_i64).unwrap_or(0),
+            msg_count: field("msg-count").and_then(Edn::as_i64).unwrap_or(0),
+            msgs_per_op: field("msgs-per-op").and_then(Edn::as_f64).unwrap_or(0.0),
+        }
+    }
+}
+
+/// This represents the parts of a Maelstrom test's `results.edn` that tests assert on.
+/// The whole document is kept in `raw` for anything else.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Results {
+    pub valid: Validity,
+    pub stats: OpStats,
+    /// The messages between all nodes, clients included.
+    pub net_all: NetStats,
+    /// The messages between server nodes only.
+    pub net_servers: NetStats,
+    /// The stable latencies in milliseconds as pairs of quantile and latency, sorted by quantile.
+    /// Only workloads that measure them, such as broadcast, have these.
+    pub stable_latencies: Vec<(f64, f64)>,
+    pub raw: Edn,
+}
+
+impl Results {
+    /// This parses the contents of a `results.edn` file.
+    pub fn parse(s: &str) -> Result<Self, ResultsError> {
+        let raw = Edn::parse(s)?;
+        if raw.get("valid?").is_none() {
+            return Err(ResultsError::Missing(":valid?"));
+        }
+        let stats = raw.get("stats");
+        let count = |name| {
+            stats
+                .and_then(|s| s.get(name))
+                .and_then(Edn::as_i64)
+                .unwrap_or(0)
+        };
+        let mut stable_latencies = match raw.get_in(&["workload", "stable-latencies"]) {
+            Some(Edn::Map(entries)) => entries
+                .iter()
+                .filter_map(|(q, l)| Some((q.as_f64()?, l.as_f64()?)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        stable_latencies.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        Ok(Self {
+            valid: Validity::from_edn(raw.get("valid?")),
+            stats: OpStats {
+                count: count("count"),
+                ok_count: count("ok-count"),
+                fail_count: count("fail-count"),
+                info_count: count("info-count"),
+            },
+            net_all: NetStats::from_edn(raw.get_in(&["net", "all"])),
+            net_servers: NetStats::from_edn(raw.get_in(&["net", "servers"])),
+            stable_latencies,
+            raw,
+        })
+    }
+
+    /// This reads the results of a test from its directory in Maelstrom's store,
+    /// such as `store/latest`.
+    pub fn load(dir: impl AsRef<Path>) -> Result<Self, ResultsError> {
+        Self::parse(&fs::read_to_string(dir.as_ref().join("results.edn"))?)
+    }
+
+    /// This reads the results of the most recent test in the store.
+    pub fn latest(store: impl AsRef<Path>) -> Result<Self, ResultsError> {
+        Self::load(store.as_ref().join("latest"))
+    }
+
+    /// The stable latency at the quantile, such as `0.99`, if it was measured.
+    pub fn stable_latency(&self, quantile: f64) -> Option<f64> {
+        self.stable_latencies
+            .iter()
+            .find(|(q, _)| *q == quantile)
+            .map(|(_, l)| *l)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An excerpt of the `results.edn` of a broadcast test, with the per-operation stats cut down.
+    const BROADCAST: &str = r#"{:perf {:latency-graph {:valid? true},
+        :rate-graph {:valid? true},
+        :valid? true},
+ :timeline {:valid? true},
+ :exceptions {:valid? true},
+ :stats {:valid? true,
+         :count 2034,
+         :ok-count 2031,
+         :fail-count 0,
+         :info-count 3,
+         :by-f {:broadcast {:valid? true, :count 1017, :ok-count 1017, :fail-count 0, :info-count 0},
+                :read {:valid? true, :count 1017, :ok-count 1014, :fail-count 0, :info-count 3}}},
+ :availability {:valid? true, :ok-fraction 677/678},
+ :net {:all {:send-count 40306, :recv-count 40306, :msg-count 40306, :msgs-per-op 19.815142},
+       :clients {:send-count 4130, :recv-count 4130, :msg-count 4130},
+       :servers {:send-count 36176, :recv-count 36176, :msg-count 36176, :msgs-per-op 17.785643},
+       :valid? true},
+ :workload {:worst-stale ({:element 42,
+                           :outcome :stable,
+                           :stable-latency 742,
+                           :known #jepsen.history.Op{:index 96, :time 4811063207, :type :ok, :process 2, :f :read, :value [0 1 42]},
+                           :last-absent #jepsen.history.Op{:index 94, :time 4069603505, :type :invoke, :process 3, :f :read, :value nil}}),
+            :duplicated-count 0,
+            :valid? true,
+            :lost-count 0,
+            :lost #{},
+            :stable-count 1017,
+            :stale-count 982,
+            #_#_ :stale (0 1 2 3 4 5 6 7 8 9),
+            :never-read-count 0,
+            :stable-latencies {0.95 680, 0 0, 1 827, 0.5 391, 0.99 742},
+            :attempt-count 1017,
+            :never-read #{},
+            :duplicated {}},
+ :valid? true}
+"#;
+
+    /// An excerpt of the `results.edn` of a lin-kv test whose checker ran out of time.
+    const LIN_KV: &str = r#"{:workload {:valid? :unknown,
+            :error "Analysis exhausted memory"},
+ :stats {:valid? true, :count 600, :ok-count 418, :fail-count 120, :info-count 62},
+ :net {:all {:send-count 9000, :recv-count 8900, :msg-count 9000, :msgs-per-op 15},
+       :valid? true},
+ :valid? :unknown}
+"#;
+
+    #[test]
+    fn broadcast_results_are_parsed() {
+        let results = Results::parse(BROADCAST).unwrap();
+        assert_eq!(results.valid, Validity::Valid);
+        assert_eq!(
+            results.stats,
+            OpStats {
+                count: 2034,
+                ok_count: 2031,
+                fail_count: 0,
+                info_count: 3,
+            }
+        );
+        assert_eq!(results.net_all.msg_count, 40306);
+        assert_eq!(results.net_servers.msgs_per_op, 17.785643);
+        assert_eq!(
+            results.raw.get_in(&["availability", "ok-fraction"]),
+            Some(&Edn::Float(677.0 / 678.0))
+        );
+        assert_eq!(
+            results.raw.get_in(&["workload", "lost"]),
+            Some(&Edn::Set(Vec::new()))
+        );
+        assert_eq!(results.raw.get_in(&["workload", "stale"]), None);
+        let Some(Edn::List(worst)) = results.raw.get_in(&["workload", "worst-stale"]) else {
+            panic!("no worst-stale operations");
+        };
+        assert!(
+            matches!(worst[0].get("known"), Some(Edn::Tagged(tag, _)) if tag == "jepsen.history.Op")
+        );
+    }
+
+    #[test]
+    fn stable_latencies_are_sorted_by_quantile() {
+        let results = Results::parse(BROADCAST).unwrap();
+        assert_eq!(
+            results.stable_latencies,
+            vec![
+                (0.0, 0.0),
+                (0.5, 391.0),
+                (0.95, 680.0),
+                (0.99, 742.0),
+                (1.0, 827.0)
+            ]
+        );
+        assert_eq!(results.stable_latency(0.99), Some(742.0));
+        assert_eq!(results.stable_latency(0.9), None);
+    }
+
+    #[test]
+    fn an_undecided_checker_is_unknown() {
+        let results = Results::parse(LIN_KV).unwrap();
+        assert_eq!(results.valid, Validity::Unknown);
+        assert_eq!(results.net_all.msgs_per_op, 15.0);
+        assert_eq!(results.net_servers, NetStats::default());
+        assert!(results.stable_latencies.is_empty());
+    }
+
+    #[test]
+    fn results_without_a_verdict_are_rejected() {
+        assert!(matches!(
+            Results::parse("{:stats {:count 1}}"),
+            Err(ResultsError::Missing(":valid?"))
+        ));
+        assert!(matches!(
+            Results::parse("{:valid? "),
+            Err(ResultsError::Edn(EdnError::Eof))
+        ));
+    }
+}
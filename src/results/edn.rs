@@ -0,0 +1,319 @@
+use std::{iter::Peekable, str::Chars};
+
+/// A value in the subset of EDN written by Jepsen to `results.edn`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Edn {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    /// A keyword without its leading `:`.
+    Keyword(String),
+    Symbol(String),
+    Char(char),
+    List(Vec<Edn>),
+    Vector(Vec<Edn>),
+    /// A map as its entries in the order they were written.
+    Map(Vec<(Edn, Edn)>),
+    Set(Vec<Edn>),
+    /// A tagged element, such as `#inst "..."` or a record literal, with the tag it was written with.
+    Tagged(String, Box<Edn>),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum EdnError {
+    #[error("unexpected end of input")]
+    Eof,
+    #[error("unexpected character {0:?}")]
+    Unexpected(char),
+    #[error("invalid number {0}")]
+    Number(String),
+}
+
+impl Edn {
+    /// This parses a single EDN value, ignoring anything after it.
+    pub fn parse(s: &str) -> Result<Self, EdnError> {
+        let mut parser = Parser {
+            chars: s.chars().peekable(),
+        };
+        parser.value()
+    }
+
+    /// This looks up a keyword in a map.
+    pub fn get(&self, keyword: &str) -> Option<&Edn> {
+        let Edn::Map(entries) = self else {
+            return None;
+        };
+        entries
+            .iter()
+            .find(|(k, _)| matches!(k, Edn::Keyword(k) if k == keyword))
+            .map(|(_, v)| v)
+    }
+
+    /// This looks up a path of keywords through nested maps.
+    pub fn get_in(&self, path: &[&str]) -> Option<&Edn> {
+        path.iter()
+            .try_fold(self, |value, keyword| value.get(keyword))
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Edn::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Edn::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// This returns the value as a float, converting integers.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Edn::Int(i) => Some(*i as f64),
+            Edn::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    /// This skips whitespace, commas, comments and discarded `#_` elements.
+    fn skip(&mut self) -> Result<(), EdnError> {
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() || c == ',' {
+                self.chars.next();
+            } else if c == ';' {
+                while self.chars.next_if(|&c| c != '\n').is_some() {}
+            } else if c == '#' {
+                let mut ahead = self.chars.clone();
+                ahead.next();
+                if ahead.peek() != Some(&'_') {
+                    break;
+                }
+                self.chars.next();
+                self.chars.next();
+                self.value()?;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn value(&mut self) -> Result<Edn, EdnError> {
+        self.skip()?;
+        let c = *self.chars.peek().ok_or(EdnError::Eof)?;
+        match c {
+            '(' => self.sequence(')').map(Edn::List),
+            '[' => self.sequence(']').map(Edn::Vector),
+            '{' => self.map(),
+            '"' => self.string(),
+            ':' => {
+                self.chars.next();
+                Ok(Edn::Keyword(self.token()))
+            }
+            '\\' => {
+                self.chars.next();
+                let token = self.token();
+                let c = match token.as_str() {
+                    "newline" => '\n',
+                    "space" => ' ',
+                    "tab" => '\t',
+                    "return" => '\r',
+                    _ => token.chars().next().ok_or(EdnError::Eof)?,
+                };
+                Ok(Edn::Char(c))
+            }
+            '#' => {
+                self.chars.next();
+                if self.chars.peek() == Some(&'{') {
+                    return self.sequence_after('{', '}').map(Edn::Set);
+                }
+                let tag = self.token();
+                let value = self.value()?;
+                Ok(Edn::Tagged(tag, Box::new(value)))
+            }
+            ')' | ']' | '}' => Err(EdnError::Unexpected(c)),
+            c if c.is_ascii_digit() => self.number(),
+            '-' | '+' => {
+                let mut ahead = self.chars.clone();
+                ahead.next();
+                match ahead.peek() {
+                    Some(d) if d.is_ascii_digit() => self.number(),
+                    _ => Ok(Edn::Symbol(self.token())),
+                }
+            }
+            _ => Ok(match self.token().as_str() {
+                "nil" => Edn::Nil,
+                "true" => Edn::Bool(true),
+                "false" => Edn::Bool(false),
+                "" => return Err(EdnError::Unexpected(c)),
+                symbol => Edn::Symbol(symbol.to_string()),
+            }),
+        }
+    }
+
+    /// This reads characters up to the next delimiter.
+    fn token(&mut self) -> String {
+        let mut token = String::new();
+        while let Some(c) = self.chars.next_if(|&c| {
+            !c.is_whitespace() && !matches!(c, ',' | '(' | ')' | '[' | ']' | '{' | '}' | '"' | ';')
+        }) {
+            token.push(c);
+        }
+        token
+    }
+
+    fn number(&mut self) -> Result<Edn, EdnError> {
+        let token = self.token();
+        let digits = token.trim_end_matches(['N', 'M']);
+        if let Ok(i) = digits.parse() {
+            return Ok(Edn::Int(i));
+        }
+        if let Ok(f) = digits.parse() {
+            return Ok(Edn::Float(f));
+        }
+        if let Some((n, d)) = digits.split_once('/') {
+            if let (Ok(n), Ok(d)) = (n.parse::<f64>(), d.parse::<f64>()) {
+                return Ok(Edn::Float(n / d));
+            }
+        }
+        Err(EdnError::Number(token))
+    }
+
+    fn string(&mut self) -> Result<Edn, EdnError> {
+        self.chars.next();
+        let mut s = String::new();
+        loop {
+            match self.chars.next().ok_or(EdnError::Eof)? {
+                '"' => return Ok(Edn::Str(s)),
+                '\\' => match self.chars.next().ok_or(EdnError::Eof)? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    c => s.push(c),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn sequence(&mut self, close: char) -> Result<Vec<Edn>, EdnError> {
+        let open = self.chars.next().ok_or(EdnError::Eof)?;
+        self.sequence_after(open, close)
+    }
+
+    /// This reads elements up to the closing delimiter, with the opening one still to be consumed.
+    fn sequence_after(&mut self, open: char, close: char) -> Result<Vec<Edn>, EdnError> {
+        if self.chars.peek() == Some(&open) {
+            self.chars.next();
+        }
+        let mut values = Vec::new();
+        loop {
+            self.skip()?;
+            if self.chars.next_if_eq(&close).is_some() {
+                return Ok(values);
+            }
+            values.push(self.value()?);
+        }
+    }
+
+    fn map(&mut self) -> Result<Edn, EdnError> {
+        let values = self.sequence('}')?;
+        let mut entries = Vec::with_capacity(values.len() / 2);
+        let mut values = values.into_iter();
+        while let Some(k) = values.next() {
+            let v = values.next().ok_or(EdnError::Unexpected('}'))?;
+            entries.push((k, v));
+        }
+        Ok(Edn::Map(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyword(k: &str) -> Edn {
+        Edn::Keyword(k.to_string())
+    }
+
+    #[test]
+    fn scalars_are_parsed() {
+        assert_eq!(Edn::parse("nil").unwrap(), Edn::Nil);
+        assert_eq!(Edn::parse("false").unwrap(), Edn::Bool(false));
+        assert_eq!(Edn::parse("-42").unwrap(), Edn::Int(-42));
+        assert_eq!(Edn::parse("12N").unwrap(), Edn::Int(12));
+        assert_eq!(Edn::parse("2.5M").unwrap(), Edn::Float(2.5));
+        assert_eq!(Edn::parse("1.5e3").unwrap(), Edn::Float(1500.0));
+        assert_eq!(
+            Edn::parse(r#""a \"b\"\n""#).unwrap(),
+            Edn::Str("a \"b\"\n".to_string())
+        );
+        assert_eq!(Edn::parse(":valid?").unwrap(), keyword("valid?"));
+        assert_eq!(Edn::parse("\\newline").unwrap(), Edn::Char('\n'));
+        assert_eq!(Edn::parse("-").unwrap(), Edn::Symbol("-".to_string()));
+    }
+
+    #[test]
+    fn ratios_are_parsed_as_floats() {
+        assert_eq!(Edn::parse("3/4").unwrap(), Edn::Float(0.75));
+        assert!(matches!(Edn::parse("3/x"), Err(EdnError::Number(_))));
+    }
+
+    #[test]
+    fn collections_are_parsed() {
+        assert_eq!(
+            Edn::parse("(1 [2, 3] #{} #{:a})").unwrap(),
+            Edn::List(vec![
+                Edn::Int(1),
+                Edn::Vector(vec![Edn::Int(2), Edn::Int(3)]),
+                Edn::Set(Vec::new()),
+                Edn::Set(vec![keyword("a")]),
+            ])
+        );
+        assert_eq!(
+            Edn::parse("{:a 1, :b {:c nil}}").unwrap(),
+            Edn::Map(vec![
+                (keyword("a"), Edn::Int(1)),
+                (keyword("b"), Edn::Map(vec![(keyword("c"), Edn::Nil)])),
+            ])
+        );
+        assert!(matches!(Edn::parse("{:a}"), Err(EdnError::Unexpected('}'))));
+        assert!(matches!(Edn::parse("[1 2"), Err(EdnError::Eof)));
+    }
+
+    #[test]
+    fn discards_and_comments_are_skipped() {
+        assert_eq!(
+            Edn::parse("; the results\n[1 #_ {:dropped [2]} 3 #_#_ 4 5]").unwrap(),
+            Edn::Vector(vec![Edn::Int(1), Edn::Int(3)])
+        );
+    }
+
+    #[test]
+    fn tagged_literals_keep_their_tag() {
+        assert_eq!(
+            Edn::parse(r#"#inst "2024-05-01T12:00:00.000-00:00""#).unwrap(),
+            Edn::Tagged(
+                "inst".to_string(),
+                Box::new(Edn::Str("2024-05-01T12:00:00.000-00:00".to_string()))
+            )
+        );
+        let Edn::Tagged(tag, op) = Edn::parse("#jepsen.history.Op{:index 7, :f :read}").unwrap()
+        else {
+            panic!("not a tagged literal");
+        };
+        assert_eq!(tag, "jepsen.history.Op");
+        assert_eq!(op.get("index"), Some(&Edn::Int(7)));
+    }
+}
@@ -0,0 +1,36 @@
+mod batcher;
+pub mod clock;
+mod composite;
+mod context;
+mod event_loop;
+mod handlers;
+mod inbox;
+mod node;
+mod priority;
+mod profile;
+pub mod repl;
+mod rng;
+mod rpc;
+mod rtt;
+mod sequence;
+pub mod signal;
+mod timer;
+mod trace;
+mod traffic;
+
+pub use batcher::{Batcher, BATCH_WINDOW_ENV, DEFAULT_BATCH_WINDOW};
+pub use composite::Composite;
+pub use context::Context;
+pub use event_loop::{Runtime, DETERMINISTIC_ENV};
+pub use handlers::{Handlers, MessageType};
+pub use inbox::{Inbox, InboxError, DEFAULT_INBOX_CAPACITY};
+pub use node::{BoxedStateMachine, InitError, MessageError, Node, StateMachine};
+pub use priority::Priority;
+pub use profile::{Profiler, Stage, REPORT_INTERVAL};
+pub use rng::Rng;
+pub use rpc::{Expired, RetryPolicy, Rpc, WindowStats};
+pub use rtt::{Ewma, RttEstimator, INITIAL_TIMEOUT};
+pub use sequence::{Sequencer, EPOCH_FIELD, RESEND_TYPE, SEQUENCE_ENV, SEQ_FIELD};
+pub use timer::{TimerId, Timers};
+pub use trace::{Tracer, TRACE_ENV};
+pub use traffic::{TrafficLog, TRAFFIC_LOG_ENV};
@@ -0,0 +1,77 @@
+use std::{collections::BTreeMap, env, mem, time::Duration};
+
+/// The environment variable that sets the batch window in milliseconds, where `0` sends every batch
+/// as soon as the messages that filled it are handled.
+pub const BATCH_WINDOW_ENV: &str = "VORTEX_BATCH_WINDOW";
+
+/// The batch window used when `VORTEX_BATCH_WINDOW` is unset or not a number of milliseconds.
+pub const DEFAULT_BATCH_WINDOW: Duration = Duration::from_millis(100);
+
+/// This coalesces the values to send each peer within a window into a single batch per peer,
+/// which the state machine flushes every window, e.g. from a timer,
+/// so that a workload sends one message with many values instead of one message per value.
+/// Peers are flushed in the order of their IDs, so that batches are sent in the same order on every run.
+pub struct Batcher<V> {
+    /// The time between flushes.
+    window: Duration,
+    /// The values waiting to be sent to each peer, in the order they were added.
+    pending: BTreeMap<String, Vec<V>>,
+}
+
+impl<V> Batcher<V> {
+    /// This creates a batcher that is flushed every `window`.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// This creates a batcher with the window in `VORTEX_BATCH_WINDOW`, or `DEFAULT_BATCH_WINDOW`.
+    pub fn from_env() -> Self {
+        let window = env::var(BATCH_WINDOW_ENV)
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map_or(DEFAULT_BATCH_WINDOW, Duration::from_millis);
+        Self::new(window)
+    }
+
+    /// The time between flushes.
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Whether batches are sent as soon as the messages that filled them are handled.
+    pub fn is_immediate(&self) -> bool {
+        self.window.is_zero()
+    }
+
+    /// This adds a value to the next batch to `peer`.
+    pub fn add(&mut self, peer: &str, value: V) {
+        self.pending
+            .entry(peer.to_string())
+            .or_default()
+            .push(value);
+    }
+
+    /// The number of values waiting to be sent.
+    pub fn len(&self) -> usize {
+        self.pending.values().map(Vec::len).sum()
+    }
+
+    /// Whether no values are waiting to be sent.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// This takes the batch of every peer with values waiting to be sent.
+    pub fn flush(&mut self) -> impl Iterator<Item = (String, Vec<V>)> {
+        mem::take(&mut self.pending).into_iter()
+    }
+}
+
+impl<V> Default for Batcher<V> {
+    fn default() -> Self {
+        Self::new(DEFAULT_BATCH_WINDOW)
+    }
+}
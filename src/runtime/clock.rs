@@ -0,0 +1,51 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+/// The time the logical clock advances by for every line of input in deterministic mode,
+/// so that timers and retransmits fire after a number of lines instead of a length of time.
+pub const LOGICAL_TICK: Duration = Duration::from_millis(10);
+
+/// A clock that only moves when input arrives, along with the seed of deterministic mode.
+#[derive(Clone, Copy)]
+struct Logical {
+    start: Instant,
+    elapsed: Duration,
+    seed: u64,
+}
+
+thread_local! {
+    /// The logical clock of the node running on this thread, or `None` outside deterministic mode.
+    static LOGICAL: Cell<Option<Logical>> = const { Cell::new(None) };
+}
+
+/// The current time, which is the logical time in deterministic mode and the wall-clock time otherwise.
+/// Timers and retransmits are scheduled against this, so workloads should use it or `Context::now` instead of `Instant::now`.
+pub fn now() -> Instant {
+    LOGICAL
+        .get()
+        .map_or_else(Instant::now, |logical| logical.start + logical.elapsed)
+}
+
+/// The seed of deterministic mode, or `None` outside of it.
+pub fn seed() -> Option<u64> {
+    LOGICAL.get().map(|logical| logical.seed)
+}
+
+/// This switches the thread to a logical clock that starts now and only advances with `tick`.
+pub(crate) fn start_logical(seed: u64) {
+    LOGICAL.set(Some(Logical {
+        start: Instant::now(),
+        elapsed: Duration::ZERO,
+        seed,
+    }));
+}
+
+/// This advances the logical clock by `LOGICAL_TICK`, which does nothing outside deterministic mode.
+pub(crate) fn tick() {
+    if let Some(mut logical) = LOGICAL.get() {
+        logical.elapsed += LOGICAL_TICK;
+        LOGICAL.set(Some(logical));
+    }
+}
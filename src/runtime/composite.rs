@@ -0,0 +1,350 @@
+use crate::{
+    log,
+    log::Level,
+    protocol::{ErrorCode, Message, Payload},
+    runtime::{BoxedStateMachine, Context, Priority, StateMachine},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{error, marker::PhantomData, time::Instant};
+
+/// This represents a state machine hosting several workloads in one node.
+/// Custom messages, and key-value messages with the `kv` feature, are routed to a workload by their `workload` field if present,
+/// and otherwise by the first workload with a prefix of their `type`.
+/// The timers of the workloads are merged, and the payload of each timer is routed back to its workload by its `workload` field.
+#[derive(Default)]
+pub struct Composite {
+    routes: Vec<Route>,
+}
+
+/// A workload registered with a composite state machine.
+struct Route {
+    /// The name of the workload matched against a message's `workload` field.
+    workload: String,
+    /// The prefixes matched against a message's `type`.
+    prefixes: Vec<String>,
+    /// The workload's state machine.
+    state_machine: BoxedStateMachine<Value>,
+}
+
+/// This adapts a state machine over typed messages to one over JSON values.
+struct Typed<T, S> {
+    state_machine: S,
+    _data: PhantomData<fn(T) -> T>,
+}
+
+impl Composite {
+    /// This creates a composite state machine without any workloads.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// This registers a workload's state machine under a name
+    /// and the prefixes of the message types it handles.
+    pub fn register<T, S>(mut self, workload: &str, prefixes: &[&str], state_machine: S) -> Self
+    where
+        T: Clone + Serialize + DeserializeOwned + 'static,
+        S: StateMachine<T> + 'static,
+    {
+        self.routes.push(Route {
+            workload: workload.to_string(),
+            prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+            state_machine: Box::new(Typed {
+                state_machine,
+                _data: PhantomData,
+            }),
+        });
+        self
+    }
+
+    fn route(&mut self, body: &Value) -> Option<&mut Route> {
+        let index = self.route_index(body)?;
+        self.routes.get_mut(index)
+    }
+
+    fn route_index(&self, body: &Value) -> Option<usize> {
+        if let Some(workload) = body.get("workload").and_then(Value::as_str) {
+            return self.routes.iter().position(|r| r.workload == workload);
+        }
+        let kind = body.get("type").and_then(Value::as_str)?;
+        self.routes
+            .iter()
+            .position(|r| r.prefixes.iter().any(|p| kind.starts_with(p.as_str())))
+    }
+}
+
+/// This returns the body of a message that is routed to a workload.
+fn routed_body(message: &Message<Value>) -> Option<Value> {
+    match &message.body.payload {
+        Payload::Custom(body) => Some(body.clone()),
+        #[cfg(feature = "kv")]
+        Payload::Kv(kv) => serde_json::to_value(kv).ok(),
+        _ => None,
+    }
+}
+
+impl StateMachine<Value> for Composite {
+    type Error = Box<dyn error::Error>;
+
+    fn on_init(&mut self, node_id: &str, node_ids: &[String]) -> Result<(), Box<dyn error::Error>> {
+        for route in &mut self.routes {
+            route.state_machine.on_init(node_id, node_ids)?;
+        }
+        Ok(())
+    }
+
+    fn on_topology(&mut self, neighbors: &[String]) -> Result<(), Box<dyn error::Error>> {
+        for route in &mut self.routes {
+            route.state_machine.on_topology(neighbors)?;
+        }
+        Ok(())
+    }
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Value>>,
+        ctx: &mut Context<Value>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        for message in messages {
+            let Some(body) = routed_body(&message) else {
+                continue;
+            };
+            if let Some(route) = self.route(&body) {
+                route.state_machine.apply(vec![message], ctx)?;
+            } else if message.body.msg_id.is_some() {
+                ctx.push(
+                    message
+                        .error_reply(ErrorCode::NotSupported, "no workload handles this message"),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn expects_reply(&self, message: &Message<Value>) -> bool {
+        routed_body(message)
+            .and_then(|body| self.route_index(&body))
+            .is_some_and(|index| self.routes[index].state_machine.expects_reply(message))
+    }
+
+    fn priority(&self, message: &Message<Value>) -> Priority {
+        routed_body(message)
+            .and_then(|body| self.route_index(&body))
+            .map_or(Priority::Normal, |index| {
+                self.routes[index].state_machine.priority(message)
+            })
+    }
+
+    fn on_reply(
+        &mut self,
+        request: Message<Value>,
+        reply: Message<Value>,
+        ctx: &mut Context<Value>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let Some(route) = routed_body(&request).and_then(|body| self.route(&body)) else {
+            return Ok(());
+        };
+        route.state_machine.on_reply(request, reply, ctx)
+    }
+
+    fn on_abandoned(
+        &mut self,
+        request: Message<Value>,
+        ctx: &mut Context<Value>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let Some(route) = routed_body(&request).and_then(|body| self.route(&body)) else {
+            return Ok(());
+        };
+        route.state_machine.on_abandoned(request, ctx)
+    }
+
+    fn on_admin(&mut self, request: &Value) -> Result<Option<Value>, Box<dyn error::Error>> {
+        match self.route(request) {
+            Some(route) => route.state_machine.on_admin(request),
+            None => Ok(None),
+        }
+    }
+
+    fn next_timer(&mut self) -> Option<Instant> {
+        self.routes
+            .iter_mut()
+            .filter_map(|route| route.state_machine.next_timer())
+            .min()
+    }
+
+    /// The payloads of the timers of every workload are tagged with the workload's name,
+    /// so that they are routed back to it when applied.
+    fn poll_timers(&mut self, now: Instant) -> Vec<(Instant, Value)> {
+        let mut fired = Vec::new();
+        for route in &mut self.routes {
+            for (deadline, mut payload) in route.state_machine.poll_timers(now) {
+                if let Value::Object(body) = &mut payload {
+                    body.insert("workload".into(), Value::String(route.workload.clone()));
+                }
+                fired.push((deadline, payload));
+            }
+        }
+        fired.sort_by_key(|(deadline, _)| *deadline);
+        fired
+    }
+
+    /// The snapshot maps the name of every workload that supports snapshots to its snapshot.
+    fn snapshot(&self) -> Option<Value> {
+        let snapshots: serde_json::Map<String, Value> = self
+            .routes
+            .iter()
+            .filter_map(|route| Some((route.workload.clone(), route.state_machine.snapshot()?)))
+            .collect();
+        (!snapshots.is_empty()).then_some(Value::Object(snapshots))
+    }
+}
+
+impl<T, S> StateMachine<Value> for Typed<T, S>
+where
+    T: Clone + Serialize + DeserializeOwned,
+    S: StateMachine<T>,
+{
+    type Error = Box<dyn error::Error>;
+
+    fn on_init(&mut self, node_id: &str, node_ids: &[String]) -> Result<(), Box<dyn error::Error>> {
+        self.state_machine
+            .on_init(node_id, node_ids)
+            .map_err(Into::into)
+    }
+
+    fn on_topology(&mut self, neighbors: &[String]) -> Result<(), Box<dyn error::Error>> {
+        self.state_machine
+            .on_topology(neighbors)
+            .map_err(Into::into)
+    }
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Value>>,
+        ctx: &mut Context<Value>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut typed = ctx.fork();
+        let messages: Vec<Message<T>> = messages
+            .into_iter()
+            .filter_map(|message| parse(message, &mut typed))
+            .collect();
+        self.state_machine
+            .apply(messages, &mut typed)
+            .map_err(Into::into)?;
+        forward(typed, ctx)
+    }
+
+    fn expects_reply(&self, message: &Message<Value>) -> bool {
+        message
+            .clone()
+            .try_map(serde_json::from_value)
+            .is_ok_and(|message| self.state_machine.expects_reply(&message))
+    }
+
+    fn priority(&self, message: &Message<Value>) -> Priority {
+        message
+            .clone()
+            .try_map(serde_json::from_value)
+            .map_or(Priority::Normal, |message| {
+                self.state_machine.priority(&message)
+            })
+    }
+
+    fn on_reply(
+        &mut self,
+        request: Message<Value>,
+        reply: Message<Value>,
+        ctx: &mut Context<Value>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut typed = ctx.fork();
+        let (Some(request), Some(reply)) = (parse(request, &mut typed), parse(reply, &mut typed))
+        else {
+            return Ok(());
+        };
+        self.state_machine
+            .on_reply(request, reply, &mut typed)
+            .map_err(Into::into)?;
+        forward(typed, ctx)
+    }
+
+    fn on_abandoned(
+        &mut self,
+        request: Message<Value>,
+        ctx: &mut Context<Value>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut typed = ctx.fork();
+        let Some(request) = parse(request, &mut typed) else {
+            return Ok(());
+        };
+        self.state_machine
+            .on_abandoned(request, &mut typed)
+            .map_err(Into::into)?;
+        forward(typed, ctx)
+    }
+
+    fn on_admin(&mut self, request: &Value) -> Result<Option<Value>, Box<dyn error::Error>> {
+        self.state_machine.on_admin(request).map_err(Into::into)
+    }
+
+    fn next_timer(&mut self) -> Option<Instant> {
+        self.state_machine.next_timer()
+    }
+
+    fn poll_timers(&mut self, now: Instant) -> Vec<(Instant, Value)> {
+        self.state_machine
+            .poll_timers(now)
+            .into_iter()
+            .filter_map(|(deadline, payload)| Some((deadline, serde_json::to_value(payload).ok()?)))
+            .collect()
+    }
+
+    fn snapshot(&self) -> Option<Value> {
+        self.state_machine.snapshot()
+    }
+}
+
+/// This parses a message routed to a workload into the workload's messages,
+/// dropping it if it is malformed and replying `malformed_request` if it is a request.
+fn parse<T>(message: Message<Value>, ctx: &mut Context<T>) -> Option<Message<T>>
+where
+    T: DeserializeOwned,
+{
+    let (src, dest, msg_id) = (
+        message.src.clone(),
+        message.dest.clone(),
+        message.body.msg_id,
+    );
+    let err = match message.try_map(serde_json::from_value) {
+        Ok(message) => return Some(message),
+        Err(err) => err,
+    };
+    log!(
+        Level::Warn,
+        "rejecting malformed message from {}: {}",
+        src,
+        err
+    );
+    if msg_id.is_some() {
+        let mut reply = Message::new(
+            &dest,
+            &src,
+            Payload::error(ErrorCode::MalformedRequest, err.to_string()),
+        );
+        reply.body.in_reply_to = msg_id;
+        ctx.push(reply);
+    }
+    None
+}
+
+/// This converts the messages sent by a workload's state machine into JSON values and sends them,
+/// carrying over the msg_ids it allocated.
+fn forward<T>(typed: Context<T>, ctx: &mut Context<Value>) -> Result<(), Box<dyn error::Error>>
+where
+    T: Serialize,
+{
+    ctx.msg_id_counter = typed.msg_id_counter;
+    for message in typed.into_messages() {
+        ctx.push(message.try_map(serde_json::to_value)?);
+    }
+    Ok(())
+}
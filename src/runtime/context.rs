@@ -0,0 +1,122 @@
+use crate::protocol::{Message, Payload};
+use std::time::Instant;
+
+/// This is what a state machine's handlers get from the node while they handle messages and timers:
+/// the node's ID and peers, the node's msg_id allocator, the time the messages are handled at,
+/// and the messages sent so far, so that the state machine only holds the workload's state.
+/// Handlers send messages as they go, including from helpers deep in their logic,
+/// instead of building up and returning a list of responses.
+/// The node gives the messages without a msg_id their msg_ids, registers the requests
+/// it expects replies to and writes them once the handler returns.
+pub struct Context<T> {
+    /// The ID of the node the messages are sent from.
+    node_id: String,
+    /// The nodes in the cluster including this node.
+    peers: Vec<String>,
+    /// The time the messages are handled at.
+    now: Instant,
+    /// The msg_id of the last message created by the node.
+    pub(crate) msg_id_counter: usize,
+    /// The messages sent so far, in the order they were sent.
+    messages: Vec<Message<T>>,
+}
+
+impl<T> Context<T> {
+    /// This creates a context for the node with nothing sent yet.
+    pub fn new(node_id: &str, peers: &[String], now: Instant) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            peers: peers.to_vec(),
+            now,
+            msg_id_counter: 0,
+            messages: Vec::new(),
+        }
+    }
+
+    /// This creates an empty context for the same node and time, sharing the msg_id allocator,
+    /// e.g. to handle messages of a different type.
+    pub(crate) fn fork<U>(&self) -> Context<U> {
+        Context {
+            node_id: self.node_id.clone(),
+            peers: self.peers.clone(),
+            now: self.now,
+            msg_id_counter: self.msg_id_counter,
+            messages: Vec::new(),
+        }
+    }
+
+    /// The ID of the node.
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    /// The nodes in the cluster including this node.
+    pub fn peers(&self) -> &[String] {
+        &self.peers
+    }
+
+    /// The nodes in the cluster other than this node.
+    pub fn others(&self) -> impl Iterator<Item = &String> {
+        self.peers.iter().filter(|peer| **peer != self.node_id)
+    }
+
+    /// The time the messages are handled at.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    /// This allocates the next msg_id of the node.
+    pub fn next_msg_id(&mut self) -> usize {
+        self.msg_id_counter += 1;
+        self.msg_id_counter
+    }
+
+    /// This sends a message.
+    pub fn push(&mut self, message: Message<T>) {
+        self.messages.push(message);
+    }
+
+    /// This sends a message from the node to `dest`.
+    pub fn send(&mut self, dest: &str, payload: impl Into<Payload<T>>) {
+        self.messages
+            .push(Message::new(&self.node_id, dest, payload));
+    }
+
+    /// This sends a message from the node to every other node in the cluster.
+    pub fn broadcast(&mut self, payload: impl Into<Payload<T>>)
+    where
+        T: Clone,
+    {
+        let payload = payload.into();
+        let others: Vec<String> = self.others().cloned().collect();
+        for peer in others {
+            self.send(&peer, payload.clone());
+        }
+    }
+
+    /// This sends a reply to the message.
+    pub fn reply(&mut self, message: &Message<T>, payload: impl Into<Payload<T>>) {
+        self.messages.push(message.reply(payload));
+    }
+
+    /// The number of messages sent so far.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Whether no messages have been sent yet.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// This returns the messages sent so far in the order they were sent.
+    pub fn into_messages(self) -> Vec<Message<T>> {
+        self.messages
+    }
+}
+
+impl<T> Extend<Message<T>> for Context<T> {
+    fn extend<I: IntoIterator<Item = Message<T>>>(&mut self, messages: I) {
+        self.messages.extend(messages);
+    }
+}
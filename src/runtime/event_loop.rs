@@ -0,0 +1,460 @@
+use crate::{
+    auth::Authenticator,
+    error::Error,
+    hlc::Stamper,
+    log,
+    log::Level,
+    protocol::{ErrorCode, Message, Payload},
+    runtime::{
+        clock, repl, signal, Inbox, MessageError, Node, Profiler, RetryPolicy, Sequencer, Stage,
+        StateMachine, Tracer, TrafficLog, DEFAULT_INBOX_CAPACITY,
+    },
+    topology::Topology,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    env,
+    io::{self, Read, Write},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Instant,
+};
+
+/// The argument that starts a node in the REPL instead of under Maelstrom.
+const REPL_ARG: &str = "--repl";
+
+/// The environment variable holding the seed that makes a node's output depend only on its input,
+/// for diffing the output of a recorded transcript against a golden copy.
+/// Timers and retransmits are driven by a logical clock that advances by `clock::LOGICAL_TICK` for every line of input
+/// instead of by when messages arrive, random choices are seeded from the seed,
+/// and the nonces of signed messages start from the seed instead of the current time.
+pub const DETERMINISTIC_ENV: &str = "VORTEX_DETERMINISTIC";
+
+/// This creates a node's state machine from the node's ID.
+type Factory<S> = Box<dyn FnOnce(&str) -> S>;
+
+/// This expands an abbreviated REPL command into a payload.
+type Expand<T> = Box<dyn Fn(&[&str]) -> Option<T>>;
+
+/// This builds the overlay of the cluster from the nodes in it.
+type Overlay = Box<dyn Fn(&[String]) -> Topology>;
+
+/// This runs a node over stdin and stdout, owning the cycle of reading messages,
+/// handling the init handshake, applying messages to the state machine and writing the responses,
+/// so that a workload binary only defines its state machine.
+/// The cycle also takes care of the traffic log, trace tags, sequence numbers, authentication,
+/// state snapshots and deterministic output, which are configured with environment variables.
+/// Started with `--repl`, the node runs in the REPL instead.
+pub struct Runtime<T, S> {
+    state_machine: Factory<S>,
+    expand: Expand<T>,
+    /// Whether messages between nodes are signed and verified if `VORTEX_AUTH_KEY` is set.
+    authenticated: bool,
+    /// How the requests that the state machine expects a reply to are retransmitted.
+    retries: RetryPolicy,
+    /// The types of the admin messages passed to the state machine's `on_admin`.
+    admin: Vec<String>,
+    /// The maximum number of requests to each peer awaiting a reply at a time.
+    window: Option<usize>,
+    /// The overlay used instead of the topology suggested by Maelstrom.
+    overlay: Option<Overlay>,
+    /// The maximum number of messages read ahead of the ones being handled.
+    inbox_capacity: usize,
+}
+
+impl<T, S> Runtime<T, S>
+where
+    T: Clone + Serialize + DeserializeOwned + 'static,
+    S: StateMachine<T>,
+{
+    /// This creates a runtime for the state machine created from the node's ID.
+    pub fn new(state_machine: impl FnOnce(&str) -> S + 'static) -> Self {
+        Self {
+            state_machine: Box::new(state_machine),
+            expand: Box::new(|_| None),
+            authenticated: false,
+            retries: RetryPolicy::default(),
+            admin: Vec::new(),
+            window: None,
+            overlay: None,
+            inbox_capacity: DEFAULT_INBOX_CAPACITY,
+        }
+    }
+
+    /// This sets how the REPL expands abbreviated commands into payloads.
+    /// Without it the REPL only accepts raw JSON messages and its built-in commands.
+    pub fn with_repl(mut self, expand: impl Fn(&[&str]) -> Option<T> + 'static) -> Self {
+        self.expand = Box::new(expand);
+        self
+    }
+
+    /// This signs and verifies the messages between nodes with the key in `VORTEX_AUTH_KEY`,
+    /// dropping messages from other nodes that fail verification.
+    pub fn authenticated(mut self) -> Self {
+        self.authenticated = true;
+        self
+    }
+
+    /// This retransmits the requests that the state machine expects a reply to
+    /// until a reply arrives or the policy's retry limit is reached.
+    pub fn with_retries(mut self, retries: RetryPolicy) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// This holds back requests to a peer while `window` of them are awaiting a reply,
+    /// sending them as replies arrive, so that a fast node does not overwhelm a slow peer.
+    /// Requests given up on after the retry limit also make room in the window.
+    pub fn with_window(mut self, window: usize) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// This makes the node talk to its neighbors in the overlay built from the nodes in the cluster,
+    /// e.g. `Topology::spanning_tree`, instead of the ones suggested by Maelstrom's topology message.
+    pub fn with_topology(mut self, overlay: impl Fn(&[String]) -> Topology + 'static) -> Self {
+        self.overlay = Some(Box::new(overlay));
+        self
+    }
+
+    /// This routes messages of the given types to the state machine's `on_admin` before normal dispatch,
+    /// so that operational requests do not need to be part of the workload's message types.
+    pub fn with_admin(mut self, types: &[&str]) -> Self {
+        self.admin.extend(types.iter().map(|t| t.to_string()));
+        self
+    }
+
+    /// This buffers at most `capacity` messages that arrive before the init message
+    /// and stops reading while `capacity` of them are waiting to be handled, instead of `DEFAULT_INBOX_CAPACITY`.
+    pub fn with_inbox_capacity(mut self, capacity: usize) -> Self {
+        self.inbox_capacity = capacity;
+        self
+    }
+
+    /// This runs the node over stdin and stdout until stdin is closed.
+    pub fn run(self) -> Result<(), Error> {
+        let mut stdout = io::stdout().lock();
+        if env::args().any(|arg| arg == REPL_ARG) {
+            let mut stdin = io::stdin().lock();
+            return repl::run(self.state_machine, self.expand, &mut stdin, &mut stdout);
+        }
+        self.serve(io::stdin(), &mut stdout)
+    }
+
+    /// This runs the node over a reader and a writer until the reader is exhausted.
+    /// The reader is read on its own thread so that requests can be retransmitted while waiting for input,
+    /// which stops reading while the inbox capacity of messages are waiting to be handled.
+    pub fn serve(
+        self,
+        reader: impl Read + Send + 'static,
+        writer: &mut impl Write,
+    ) -> Result<(), Error> {
+        signal::install()?;
+        let seed = match env::var(DETERMINISTIC_ENV) {
+            Ok(seed) => Some(seed.parse::<u64>().map_err(|e| Error::Env {
+                var: DETERMINISTIC_ENV,
+                reason: e.to_string(),
+            })?),
+            Err(_) => None,
+        };
+        if let Some(seed) = seed {
+            clock::start_logical(seed);
+        }
+        let (sender, receiver) = mpsc::sync_channel(self.inbox_capacity);
+        thread::spawn(move || {
+            for message in Message::<Value>::stream(reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut messages = receiver.iter();
+        let mut inbox = Inbox::new(self.inbox_capacity);
+        let init = inbox.wait_for_init(&mut messages)?;
+        let mut log = TrafficLog::from_env(&init.dest)?;
+        log.recv(&init)?;
+        log_message("recv", &init);
+        let (mut node, resp) =
+            Node::init(init.try_map(serde_json::from_value)?, self.state_machine)?;
+        node.set_retry_policy(self.retries);
+        node.set_window(self.window);
+        log::set_node_id(node.id());
+        log!(Level::Info, "initialized with {} nodes", node.peers().len());
+        node.set_overlay(self.overlay.map(|overlay| overlay(node.peers())));
+        log.send(&resp)?;
+        log_message("send", &resp);
+        resp.write(writer)?;
+        let mut wire = Wire {
+            log,
+            tracer: Tracer::from_env(node.id(), node.peers()),
+            sequencer: Sequencer::from_env(node.id(), node.peers()),
+            hlc: Stamper::from_env(node.id(), node.peers()),
+            profiler: Profiler::new(),
+            auth: match (self.authenticated, seed) {
+                (true, Some(seed)) => Authenticator::from_env(node.peers()).starting_at(seed),
+                (true, None) => Authenticator::from_env(node.peers()),
+                (false, _) => Authenticator::disabled(),
+            },
+        };
+
+        for mut reply in inbox.take_rejected() {
+            reply.body.msg_id = Some(node.next_msg_id());
+            wire.send(&reply, writer)?;
+        }
+        let mut buffered = Vec::new();
+        for message in inbox.drain() {
+            let Some(message) = wire.recv(message, writer)? else {
+                continue;
+            };
+            if is_admin(&self.admin, &message) {
+                if let Some(res) = node.admin(&message)? {
+                    wire.send(&res, writer)?;
+                }
+                continue;
+            }
+            if let Some(message) = parse(message, &mut node, &mut wire, writer)? {
+                buffered.push(message);
+            }
+        }
+        if !buffered.is_empty() {
+            log!(Level::Debug, "applying {} messages", buffered.len());
+        }
+        for res in wire
+            .profiler
+            .time(Stage::Apply, || node.recv_messages(buffered))?
+        {
+            wire.send(&res, writer)?;
+        }
+
+        loop {
+            let deadline = match seed {
+                Some(_) => None,
+                None => node.next_deadline(),
+            };
+            let message = match deadline {
+                Some(deadline) => {
+                    match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                    {
+                        Ok(message) => Some(message),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match receiver.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                },
+            };
+            if let Some(message) = message {
+                // The messages that have already arrived are applied together,
+                // so that control messages among them are applied first.
+                let mut arrived = vec![message];
+                if seed.is_none() {
+                    arrived.extend(receiver.try_iter());
+                }
+                let mut batch = Vec::new();
+                for message in arrived {
+                    clock::tick();
+                    let message = match message {
+                        Ok(message) => message,
+                        Err(MessageError::Read(err)) => return Err(err.into()),
+                        Err(err) => {
+                            log!(Level::Warn, "dropping malformed message: {}", err);
+                            if let Some(mut reply) = err.error_reply() {
+                                reply.body.msg_id = Some(node.next_msg_id());
+                                wire.send(&reply, writer)?;
+                            }
+                            continue;
+                        }
+                    };
+                    match wire.recv(message, writer)? {
+                        Some(message) if is_admin(&self.admin, &message) => {
+                            if let Some(res) = node.admin(&message)? {
+                                wire.send(&res, writer)?;
+                            }
+                        }
+                        Some(message) => {
+                            if let Some(message) = parse(message, &mut node, &mut wire, writer)? {
+                                batch.push(message);
+                            }
+                        }
+                        None => {}
+                    }
+                }
+                if !batch.is_empty() {
+                    log!(Level::Debug, "applying {} messages", batch.len());
+                    let responses = wire
+                        .profiler
+                        .time(Stage::Apply, || node.recv_messages(batch))?;
+                    for res in responses {
+                        wire.send(&res, writer)?;
+                    }
+                }
+            }
+            let responses = wire
+                .profiler
+                .time(Stage::Apply, || node.poll(clock::now()))?;
+            for res in responses {
+                wire.send(&res, writer)?;
+            }
+            if let Some(path) = signal::dump_if_requested(&node)? {
+                log!(Level::Info, "wrote state snapshot to {}", path.display());
+            }
+            wire.profiler.report_if_due();
+        }
+        wire.profiler.report();
+        Ok(())
+    }
+}
+
+/// This parses the payload of a message into the workload's type.
+/// A message that is not one of the workload's messages returns `None` instead of stopping the node,
+/// and is replied to with a malformed request error if it has a msg_id.
+fn parse<T, S>(
+    message: Message<Value>,
+    node: &mut Node<T, S>,
+    wire: &mut Wire,
+    writer: &mut impl Write,
+) -> Result<Option<Message<T>>, Error>
+where
+    T: DeserializeOwned,
+    S: StateMachine<T>,
+{
+    let mut reply = message.error_reply(ErrorCode::MalformedRequest, "");
+    let parsed = wire
+        .profiler
+        .time(Stage::Parse, || message.try_map(serde_json::from_value));
+    let err = match parsed {
+        Ok(message) => return Ok(Some(message)),
+        Err(err) => err,
+    };
+    log!(
+        Level::Warn,
+        "rejecting malformed message from {}: {}",
+        reply.dest,
+        err
+    );
+    if reply.body.in_reply_to.is_some() {
+        reply.body.payload = Payload::error(ErrorCode::MalformedRequest, err.to_string());
+        reply.body.msg_id = Some(node.next_msg_id());
+        wire.send(&reply, writer)?;
+    }
+    Ok(None)
+}
+
+/// This returns whether a message is an admin message of one of the types.
+fn is_admin(types: &[String], message: &Message<Value>) -> bool {
+    let Payload::Custom(body) = &message.body.payload else {
+        return false;
+    };
+    body.get("type")
+        .and_then(Value::as_str)
+        .is_some_and(|kind| types.iter().any(|t| t == kind))
+}
+
+/// The layers every message passes through between the node and stdin or stdout.
+struct Wire {
+    log: TrafficLog,
+    tracer: Tracer,
+    profiler: Profiler,
+    sequencer: Sequencer,
+    hlc: Stamper,
+    auth: Authenticator,
+}
+
+impl Wire {
+    /// This logs, verifies and checks the sequence number of a message that was read,
+    /// and merges its HLC timestamp,
+    /// returning `None` if it failed verification, was already received or was a resend request.
+    /// The resend requests and resent messages resulting from it are written.
+    fn recv(
+        &mut self,
+        message: Message<Value>,
+        writer: &mut impl Write,
+    ) -> Result<Option<Message<Value>>, Error> {
+        self.log.recv(&message)?;
+        log_message("recv", &message);
+        let Some(message) = self.auth.accept(message)? else {
+            return Ok(None);
+        };
+        let (message, responses) = self.sequencer.accept(message);
+        for response in responses {
+            self.write(&response, writer)?;
+        }
+        Ok(message.map(|message| self.hlc.accept(message)))
+    }
+
+    /// This stamps, sequences, signs, logs and writes a message, timing it as the serialize stage.
+    fn send<T>(&mut self, message: &Message<T>, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        let mut profiler = std::mem::take(&mut self.profiler);
+        let result = profiler.time(Stage::Serialize, || self.stamp(message, writer));
+        self.profiler = profiler;
+        result
+    }
+
+    /// This stamps, sequences, signs, logs and writes a message.
+    fn stamp<T>(&mut self, message: &Message<T>, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        if self.hlc.is_enabled() {
+            let message = self.hlc.stamp(message)?;
+            return self.sequence(&message, writer);
+        }
+        self.sequence(message, writer)
+    }
+
+    /// This sequences, signs, logs and writes a message.
+    fn sequence<T>(&mut self, message: &Message<T>, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        if self.sequencer.is_enabled() {
+            let message = self.sequencer.stamp(message)?;
+            return self.write(&message, writer);
+        }
+        self.write(message, writer)
+    }
+
+    /// This signs, logs and writes a message.
+    fn write<T>(&mut self, message: &Message<T>, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        log_message("send", message);
+        if self.auth.is_enabled() {
+            let message = self.auth.sign(message)?;
+            self.log.send(&message)?;
+            return self.tracer.write(&message, writer);
+        }
+        self.log.send(message)?;
+        self.tracer.write(message, writer)
+    }
+}
+
+/// This logs a message that was read or written with its type at the debug level,
+/// and with its whole body at the trace level.
+fn log_message<T>(event: &str, message: &Message<T>)
+where
+    T: Serialize,
+{
+    if !log::enabled(Level::Debug) {
+        return;
+    }
+    let body = serde_json::to_value(&message.body).unwrap_or_default();
+    log!(
+        Level::Debug,
+        "{} {} {} -> {} msg_id {} in_reply_to {}",
+        event,
+        body["type"].as_str().unwrap_or("unknown"),
+        message.src,
+        message.dest,
+        body["msg_id"],
+        body["in_reply_to"],
+    );
+    log!(Level::Trace, "{} body {}", event, body);
+}
@@ -0,0 +1,174 @@
+use crate::{
+    protocol::{ErrorCode, Message, Payload, Source},
+    runtime::{Context, StateMachine},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{collections::HashMap, error};
+
+/// This is a trait for the body of one type of message, which is registered with `Handlers::on`.
+/// The body is (de)serialized without its `type` field, which is `TYPE`.
+pub trait MessageType: Serialize + DeserializeOwned {
+    /// The `type` of the message's body.
+    const TYPE: &'static str;
+
+    /// This converts the body into a custom payload, adding its `type`.
+    fn to_body(&self) -> Result<Value, serde_json::Error> {
+        let mut body = serde_json::to_value(self)?;
+        if let Some(fields) = body.as_object_mut() {
+            fields.insert("type".to_string(), Self::TYPE.into());
+        }
+        Ok(body)
+    }
+}
+
+/// A handler registered for one type of message.
+type Handler<S> = Box<
+    dyn FnMut(&mut S, &Message<Value>, &mut Context<Value>) -> Result<(), Box<dyn error::Error>>,
+>;
+
+/// This represents a state machine that dispatches each custom message to the handler registered for its `type`,
+/// so that a workload defines a handler per message instead of a single `match` over all of them.
+/// Each handler gets the state, the message, the kind of node it comes from, its parsed body
+/// and the context to send its responses through.
+/// Messages whose body does not parse are replied to with a malformed request error,
+/// and requests of a type without a handler with a not-supported error.
+/// Replies without a handler are dropped, since answering them would only bounce errors between nodes.
+pub struct Handlers<S> {
+    /// The state shared by the handlers.
+    state: S,
+    /// The handlers by the type of message they handle.
+    handlers: HashMap<&'static str, Handler<S>>,
+}
+
+impl<S> Handlers<S> {
+    /// This creates a state machine over the state without any handlers.
+    pub fn new(state: S) -> Self {
+        Self {
+            state,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// This registers the handler of the messages of type `M`,
+    /// replacing any handler registered for its type before.
+    pub fn on<M, E>(
+        mut self,
+        mut handler: impl FnMut(&mut S, &Message<Value>, Source<'_>, M, &mut Context<Value>) -> Result<(), E>
+            + 'static,
+    ) -> Self
+    where
+        M: MessageType,
+        E: Into<Box<dyn error::Error>>,
+    {
+        self.handlers.insert(
+            M::TYPE,
+            Box::new(move |state, message, ctx| {
+                let Payload::Custom(body) = &message.body.payload else {
+                    return Ok(());
+                };
+                let body = match M::deserialize(body) {
+                    Ok(body) => body,
+                    Err(err) if message.body.msg_id.is_some() => {
+                        ctx.push(message.error_reply(ErrorCode::MalformedRequest, err.to_string()));
+                        return Ok(());
+                    }
+                    Err(_) => return Ok(()),
+                };
+                handler(state, message, message.source(), body, ctx).map_err(Into::into)
+            }),
+        );
+        self
+    }
+
+    /// The state shared by the handlers.
+    pub fn state(&self) -> &S {
+        &self.state
+    }
+}
+
+impl<S> StateMachine<Value> for Handlers<S>
+where
+    S: Serialize,
+{
+    type Error = Box<dyn error::Error>;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<Value>>,
+        ctx: &mut Context<Value>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        for message in messages {
+            let Payload::Custom(body) = &message.body.payload else {
+                continue;
+            };
+            let kind = body.get("type").and_then(Value::as_str).unwrap_or_default();
+            match self.handlers.get_mut(kind) {
+                Some(handler) => handler(&mut self.state, &message, ctx)?,
+                None if message.body.msg_id.is_some() && message.body.in_reply_to.is_none() => ctx
+                    .push(
+                        message.error_reply(ErrorCode::NotSupported, "no handler for this message"),
+                    ),
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self) -> Option<Value> {
+        serde_json::to_value(&self.state).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::clock;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Serialize, Deserialize)]
+    struct Echo {
+        echo: String,
+    }
+
+    impl MessageType for Echo {
+        const TYPE: &'static str = "echo";
+    }
+
+    fn node() -> Handlers<()> {
+        Handlers::new(()).on(|_, message, _, echo: Echo, ctx| {
+            ctx.push(message.reply(json!({"type": "echo_ok", "echo": echo.echo})));
+            Ok::<_, Box<dyn error::Error>>(())
+        })
+    }
+
+    fn apply(message: Message<Value>) -> Vec<Message<Value>> {
+        let mut ctx = Context::new("n1", &["n1".to_string(), "n2".to_string()], clock::now());
+        node().apply(vec![message], &mut ctx).unwrap();
+        ctx.into_messages()
+    }
+
+    #[test]
+    fn unhandled_requests_are_not_supported() {
+        let mut request = Message::new("c1", "n1", json!({"type": "read"}));
+        request.body.msg_id = Some(1);
+        let replies = apply(request);
+        assert_eq!(replies.len(), 1);
+        assert!(matches!(
+            replies[0].body.payload,
+            Payload::Error {
+                code: ErrorCode::NotSupported,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn unhandled_replies_are_dropped() {
+        let mut reply = Message::new("n2", "n1", json!({"type": "gossip_ok"}));
+        reply.body.msg_id = Some(2);
+        reply.body.in_reply_to = Some(1);
+        assert!(apply(reply).is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{
+    error::Error,
+    log,
+    log::Level,
+    protocol::{Message, Payload},
+    runtime::MessageError,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+
+/// The number of messages an inbox buffers by default.
+pub const DEFAULT_INBOX_CAPACITY: usize = 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum InboxError {
+    #[error("inbox is full after buffering {0} messages")]
+    Full(usize),
+    #[error("input ended before the init message")]
+    Closed,
+}
+
+/// This buffers messages that arrive before the node is initialized,
+/// so that they can be delivered once the init message has been handled
+/// instead of being rejected.
+pub struct Inbox<T> {
+    /// The buffered messages in the order they arrived.
+    messages: VecDeque<Message<T>>,
+    /// The maximum number of messages to buffer.
+    capacity: usize,
+    /// The error replies to the malformed messages skipped while waiting for the init message.
+    rejected: Vec<Message<Value>>,
+}
+
+impl<T> Default for Inbox<T> {
+    fn default() -> Self {
+        Self::new(DEFAULT_INBOX_CAPACITY)
+    }
+}
+
+impl<T> Inbox<T> {
+    /// This creates an inbox buffering at most `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            capacity,
+            rejected: Vec::new(),
+        }
+    }
+
+    /// This buffers a message, failing if the inbox is full.
+    pub fn push(&mut self, message: Message<T>) -> Result<(), InboxError> {
+        if self.messages.len() >= self.capacity {
+            return Err(InboxError::Full(self.messages.len()));
+        }
+        self.messages.push_back(message);
+        Ok(())
+    }
+
+    /// The number of buffered messages.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Whether there are no buffered messages.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// This removes and returns the buffered messages in the order they arrived.
+    pub fn drain(&mut self) -> Vec<Message<T>> {
+        self.messages.drain(..).collect()
+    }
+
+    /// This removes and returns the malformed request error replies to the malformed messages
+    /// skipped while waiting for the init message, which are sent once the node can give them msg_ids.
+    pub fn take_rejected(&mut self) -> Vec<Message<Value>> {
+        std::mem::take(&mut self.rejected)
+    }
+
+    /// This takes messages from the stream until the init message,
+    /// buffering every message taken before it.
+    /// Messages that fail to parse, such as a garbled line or a malformed init message,
+    /// are logged and skipped so that the node keeps waiting for a valid init message.
+    pub fn wait_for_init(
+        &mut self,
+        messages: &mut impl Iterator<Item = Result<Message<T>, MessageError>>,
+    ) -> Result<Message<T>, Error>
+    where
+        T: Serialize,
+    {
+        for message in messages {
+            let message = match message {
+                Err(MessageError::Read(err)) => return Err(err.into()),
+                Err(err) => {
+                    log!(
+                        Level::Warn,
+                        "skipping malformed message while waiting for init: {}",
+                        err
+                    );
+                    self.rejected.extend(err.error_reply());
+                    continue;
+                }
+                Ok(message) => message,
+            };
+            match &message.body.payload {
+                Payload::Init { .. } => return Ok(message),
+                // An init message that only parses as a custom payload, e.g. as a JSON value, is malformed.
+                Payload::Custom(body) if serde_json::to_value(body)?["type"] == "init" => {
+                    log!(
+                        Level::Warn,
+                        "skipping malformed init message from {}",
+                        message.src
+                    );
+                    continue;
+                }
+                _ => {}
+            }
+            self.push(message)?;
+        }
+        Err(InboxError::Closed.into())
+    }
+}
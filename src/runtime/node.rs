@@ -0,0 +1,617 @@
+use crate::{
+    error::Error,
+    protocol::{ErrorCode, Message, Payload},
+    runtime::{clock, Context, Priority, RetryPolicy, Rpc, Timers},
+    topology::Topology,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    error,
+    io::{self, BufRead, BufReader, Read, Write},
+    iter, mem,
+    time::Instant,
+};
+
+impl<T> Message<T>
+where
+    T: DeserializeOwned,
+{
+    /// This is used to deserialize a message from a buffered reader.
+    /// A line that is not a valid message is returned as `MessageError::Deserialize`.
+    pub fn from_reader(reader: &mut impl BufRead) -> Result<Self, Error> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        serde_json::from_str(&line)
+            .map_err(|source| MessageError::Deserialize { line, source }.into())
+    }
+
+    /// This is used to deserialize a stream of messages from a reader.
+    /// Messages are delimited by where their JSON ends rather than by newlines,
+    /// so a line may hold several messages and a message may span several lines.
+    /// A message that is not a valid message, including invalid JSON, is returned as
+    /// `MessageError::Deserialize` without ending the stream, which skips invalid JSON
+    /// to the next newline, and the stream only ends with the reader or a `MessageError::Read`.
+    pub fn stream(reader: impl Read) -> impl Iterator<Item = Result<Self, MessageError>> {
+        let mut reader = BufReader::new(reader);
+        let mut buffer = String::new();
+        let mut parsed = VecDeque::new();
+        let mut done = false;
+        iter::from_fn(move || loop {
+            if let Some(message) = parsed.pop_front() {
+                return Some(message);
+            }
+            if done {
+                let line = mem::take(&mut buffer).trim_end().to_string();
+                if line.is_empty() {
+                    return None;
+                }
+                let source = serde_json::from_str::<Value>(&line).err()?;
+                return Some(Err(MessageError::Deserialize { line, source }));
+            }
+            match reader.read_line(&mut buffer) {
+                Ok(0) => done = true,
+                Ok(_) => buffer = split_messages(&buffer, &mut parsed),
+                Err(err) => {
+                    done = true;
+                    buffer.clear();
+                    return Some(Err(err.into()));
+                }
+            }
+        })
+    }
+}
+
+/// This parses the messages in `buffer` into `parsed`, returning the rest of it,
+/// which is the start of a message that continues on the next line.
+/// Invalid JSON is returned as a malformed message up to the end of the line it started on,
+/// and parsing carries on from the next line.
+fn split_messages<T>(
+    buffer: &str,
+    parsed: &mut VecDeque<Result<Message<T>, MessageError>>,
+) -> String
+where
+    T: DeserializeOwned,
+{
+    let mut rest = buffer;
+    loop {
+        let mut values = serde_json::Deserializer::from_str(rest).into_iter::<Value>();
+        let start = rest.len() - rest.trim_start().len();
+        match values.next() {
+            None => return String::new(),
+            Some(Ok(value)) => {
+                let end = values.byte_offset();
+                parsed.push_back(serde_json::from_value(value).map_err(|source| {
+                    let line = rest[start..end].to_string();
+                    MessageError::Deserialize { line, source }
+                }));
+                rest = &rest[end..];
+            }
+            Some(Err(err)) if err.is_eof() => return rest.to_string(),
+            Some(Err(source)) => {
+                let end = rest[start..]
+                    .find('\n')
+                    .map_or(rest.len(), |i| start + i + 1);
+                let line = rest[start..end].trim_end().to_string();
+                parsed.push_back(Err(MessageError::Deserialize { line, source }));
+                rest = &rest[end..];
+            }
+        }
+    }
+}
+
+impl<T> Message<T>
+where
+    T: Serialize,
+{
+    /// This is used to serialize a message to a writer with a trailing newline
+    /// as specified by Maelstrom's protocol.
+    pub fn write(&self, writer: &mut impl Write) -> Result<(), Error> {
+        serde_json::to_writer(&mut *writer, self)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+/// This represents the Maelstrom node.
+pub struct Node<T, S> {
+    /// The ID of the node.
+    id: String,
+    /// The nodes in the cluster including itself.
+    peers: Vec<String>,
+    /// The nodes that this node talks to since the latest topology message.
+    neighbors: Vec<String>,
+    /// The overlay used instead of the topology suggested by topology messages, if any.
+    overlay: Option<Topology>,
+    /// The msg_id of the last message created by the node itself.
+    msg_id_counter: usize,
+    /// The requests from the state machine that are awaiting a reply.
+    rpc: Rpc<T>,
+    /// The state of the node, which is specific to the application.
+    /// This should contain the business state of the application.
+    state_machine: S,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MessageError {
+    #[error("invalid message")]
+    Invalid,
+    #[error("malformed message {line:?}: {source}")]
+    Deserialize {
+        line: String,
+        #[source]
+        source: serde_json::Error,
+    },
+    #[error("failed to read messages: {0}")]
+    Read(#[from] io::Error),
+}
+
+impl MessageError {
+    /// This creates a malformed request error reply to the message that could not be deserialized,
+    /// if its source, destination and msg_id can still be recovered from its line.
+    /// The reply has no msg_id of its own yet.
+    pub fn error_reply(&self) -> Option<Message<Value>> {
+        let MessageError::Deserialize { line, source } = self else {
+            return None;
+        };
+        let src = recover_field(line, "src")?.as_str()?.to_string();
+        let dest = recover_field(line, "dest")?.as_str()?.to_string();
+        let msg_id = recover_field(line, "msg_id")?.as_u64()? as usize;
+        let mut request = Message::new(&src, &dest, Payload::InitOk);
+        request.body.msg_id = Some(msg_id);
+        Some(request.error_reply(ErrorCode::MalformedRequest, source.to_string()))
+    }
+}
+
+/// This recovers the string or number of the first `"field": value` in a line that may not be valid JSON.
+fn recover_field(line: &str, field: &str) -> Option<Value> {
+    let key = format!("\"{}\"", field);
+    let value = line[line.find(&key)? + key.len()..]
+        .trim_start()
+        .strip_prefix(':')?
+        .trim_start();
+    if value.starts_with('"') {
+        let mut values = serde_json::Deserializer::from_str(value).into_iter::<String>();
+        return values.next()?.ok().map(Value::from);
+    }
+    let digits = value.len() - value.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    value[..digits].parse::<u64>().ok().map(Value::from)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum InitError {
+    #[error("expected an init message from {src} to {dest}")]
+    NotInit { src: String, dest: String },
+    #[error("init message names node {node_id:?} which is not in its node_ids {node_ids:?}")]
+    UnknownNode {
+        node_id: String,
+        node_ids: Vec<String>,
+    },
+}
+
+impl<T, S> Node<T, S>
+where
+    S: StateMachine<T>,
+{
+    /// This initializes the server based on an init message,
+    /// returning the node and the response to the init message.
+    /// The state machine is created from the node's ID once the init message has been validated.
+    pub fn init(
+        message: Message<T>,
+        state_machine: impl FnOnce(&str) -> S,
+    ) -> Result<(Self, Message<T>), Error> {
+        let Payload::Init { node_id, node_ids } = &message.body.payload else {
+            return Err(InitError::NotInit {
+                src: message.src,
+                dest: message.dest,
+            }
+            .into());
+        };
+        if !node_ids.contains(node_id) {
+            return Err(InitError::UnknownNode {
+                node_id: node_id.clone(),
+                node_ids: node_ids.clone(),
+            }
+            .into());
+        }
+        let mut node = Self {
+            state_machine: state_machine(node_id),
+            id: node_id.clone(),
+            peers: node_ids.clone(),
+            neighbors: Vec::new(),
+            overlay: None,
+            msg_id_counter: 0,
+            rpc: Rpc::default(),
+        };
+        node.state_machine
+            .on_init(&node.id, &node.peers)
+            .map_err(Error::handler)?;
+        let resp = node.reply_to(&message, Payload::InitOk);
+        Ok((node, resp))
+    }
+
+    /// The ID of the node.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The nodes in the cluster including this node.
+    pub fn peers(&self) -> &[String] {
+        &self.peers
+    }
+
+    /// The nodes that this node talks to since the latest topology message.
+    pub fn neighbors(&self) -> &[String] {
+        &self.neighbors
+    }
+
+    /// The requests from the state machine that are awaiting a reply.
+    pub fn rpc(&self) -> &Rpc<T> {
+        &self.rpc
+    }
+
+    /// This sets how the requests awaiting a reply are retransmitted.
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.rpc.set_policy(policy);
+    }
+
+    /// This sets the maximum number of requests to each peer awaiting a reply at a time.
+    pub fn set_window(&mut self, window: Option<usize>) {
+        self.rpc.set_window(window);
+    }
+
+    /// This sets the overlay whose neighbors are used instead of the ones suggested by topology messages.
+    pub fn set_overlay(&mut self, overlay: Option<Topology>) {
+        self.overlay = overlay;
+    }
+
+    /// This allocates the next msg_id of the node.
+    /// Every message created by the node gets its msg_id from here,
+    /// so msg_ids are unique across all of the node's handlers.
+    pub fn next_msg_id(&mut self) -> usize {
+        self.msg_id_counter += 1;
+        self.msg_id_counter
+    }
+
+    /// This creates a message from the node with the next msg_id of the node.
+    pub fn message(&mut self, dest: &str, payload: impl Into<Payload<T>>) -> Message<T> {
+        let mut message = Message::new(&self.id, dest, payload);
+        message.body.msg_id = Some(self.next_msg_id());
+        message
+    }
+
+    /// This creates a reply to the message with the next msg_id of the node.
+    pub fn reply_to(&mut self, message: &Message<T>, payload: impl Into<Payload<T>>) -> Message<T> {
+        let mut reply = message.reply(payload);
+        reply.body.msg_id = Some(self.next_msg_id());
+        reply
+    }
+
+    /// This returns a snapshot of the state machine for debugging,
+    /// or `None` if the state machine does not support snapshots.
+    pub fn snapshot(&self) -> Option<serde_json::Value> {
+        self.state_machine.snapshot()
+    }
+
+    /// This applies an admin message to the state machine,
+    /// returning the reply to it if it has a msg_id.
+    /// Admin messages the state machine does not handle are replied to with a not-supported error.
+    pub fn admin(&mut self, message: &Message<Value>) -> Result<Option<Message<Value>>, Error> {
+        let Payload::Custom(request) = &message.body.payload else {
+            return Ok(None);
+        };
+        let reply = self
+            .state_machine
+            .on_admin(request)
+            .map_err(Error::handler)?;
+        if message.body.msg_id.is_none() {
+            return Ok(None);
+        }
+        let mut reply = match reply {
+            Some(body) => message.reply(body),
+            None => message.error_reply(ErrorCode::NotSupported, "unsupported admin message"),
+        };
+        reply.body.msg_id = Some(self.next_msg_id());
+        Ok(Some(reply))
+    }
+}
+
+impl<T, S> Node<T, S>
+where
+    T: Clone,
+    S: StateMachine<T>,
+{
+    /// This applies the messages to the state machine, returning the messages it sent.
+    /// Topology messages are handled by the node itself,
+    /// which updates its neighbors and notifies the state machine before replying.
+    /// With an overlay set, the neighbors come from the overlay instead of the message.
+    /// Replies to requests that the state machine expects a reply to are passed to it with their request.
+    /// Responses from the state machine without a msg_id are given the next msg_id of the node.
+    /// Control messages are applied ahead of the rest of the messages.
+    pub fn recv_messages(
+        &mut self,
+        mut messages: Vec<Message<T>>,
+    ) -> Result<Vec<Message<T>>, Error> {
+        messages.sort_by_key(|message| Reverse(self.state_machine.priority(message)));
+        let mut ctx = self.context(clock::now());
+        let mut batch = Vec::new();
+        for message in messages {
+            if let Payload::Topology { topology } = &message.body.payload {
+                if !batch.is_empty() {
+                    self.state_machine
+                        .apply(std::mem::take(&mut batch), &mut ctx)
+                        .map_err(Error::handler)?;
+                }
+                self.neighbors = match &self.overlay {
+                    Some(overlay) => overlay.neighbors(&self.id).to_vec(),
+                    None => topology.get(&self.id).cloned().unwrap_or_default(),
+                };
+                self.state_machine
+                    .on_topology(&self.neighbors)
+                    .map_err(Error::handler)?;
+                ctx.reply(&message, Payload::TopologyOk);
+            } else if let Some(request) = self.rpc.resolve(&message, ctx.now()) {
+                if !batch.is_empty() {
+                    self.state_machine
+                        .apply(std::mem::take(&mut batch), &mut ctx)
+                        .map_err(Error::handler)?;
+                }
+                self.state_machine
+                    .on_reply(request, message, &mut ctx)
+                    .map_err(Error::handler)?;
+            } else {
+                batch.push(message);
+            }
+        }
+        if !batch.is_empty() {
+            self.state_machine
+                .apply(batch, &mut ctx)
+                .map_err(Error::handler)?;
+        }
+        Ok(self.outbound(ctx))
+    }
+
+    /// The earliest time at which a request awaiting a reply times out or a timer of the state machine fires.
+    pub fn next_deadline(&mut self) -> Option<Instant> {
+        let timers = self.state_machine.next_timer();
+        match (self.rpc.next_deadline(), timers) {
+            (Some(rpc), Some(timers)) => Some(rpc.min(timers)),
+            (rpc, timers) => rpc.or(timers),
+        }
+    }
+
+    /// This returns the requests to retransmit because their replies did not arrive by `now`,
+    /// along with the responses of the state machine to the requests given up on
+    /// and to the timers that fire by `now`.
+    /// The payload of each timer is applied to the state machine as a message from the node to itself.
+    pub fn poll(&mut self, now: Instant) -> Result<Vec<Message<T>>, Error> {
+        let expired = self.rpc.poll(now);
+        let mut ctx = self.context(now);
+        for request in expired.abandoned {
+            self.state_machine
+                .on_abandoned(request, &mut ctx)
+                .map_err(Error::handler)?;
+        }
+        let fired: Vec<Message<T>> = self
+            .state_machine
+            .poll_timers(now)
+            .into_iter()
+            .map(|(_, payload)| Message::new(&self.id, &self.id, payload))
+            .collect();
+        if !fired.is_empty() {
+            self.state_machine
+                .apply(fired, &mut ctx)
+                .map_err(Error::handler)?;
+        }
+        let mut messages = expired.retransmit;
+        messages.extend(self.outbound(ctx));
+        messages.sort_by_key(|message| Reverse(self.state_machine.priority(message)));
+        Ok(messages)
+    }
+
+    /// This creates the context the state machine handles messages and timers in at `now`.
+    fn context(&self, now: Instant) -> Context<T> {
+        let mut ctx = Context::new(&self.id, &self.peers, now);
+        ctx.msg_id_counter = self.msg_id_counter;
+        ctx
+    }
+
+    /// This takes back the msg_ids allocated in the context,
+    /// gives the messages sent in it without a msg_id the next msg_id of the node,
+    /// and registers the requests that the state machine expects a reply to.
+    /// Requests to a peer whose window is full are held back until replies make room for them,
+    /// and the held back requests that now have room are sent ahead of the responses.
+    /// Control messages are never held back and are sent ahead of everything else.
+    fn outbound(&mut self, ctx: Context<T>) -> Vec<Message<T>> {
+        let now = clock::now();
+        self.msg_id_counter = ctx.msg_id_counter;
+        let responses = ctx.into_messages();
+        let mut messages = self.rpc.release(now);
+        for mut response in responses {
+            if response.body.msg_id.is_none() {
+                response.body.msg_id = Some(self.next_msg_id());
+            }
+            if response.body.in_reply_to.is_none() && self.state_machine.expects_reply(&response) {
+                if self.state_machine.priority(&response) == Priority::Control {
+                    self.rpc.call(response.clone(), now);
+                } else if !self.rpc.admit(response.clone(), now) {
+                    continue;
+                }
+            }
+            messages.push(response);
+        }
+        messages.sort_by_key(|message| Reverse(self.state_machine.priority(message)));
+        messages
+    }
+}
+
+/// This is a trait for applications to implement how messages should affect the node's state.
+/// This should be implemented based on the application's specific needs.
+pub trait StateMachine<T> {
+    /// The error returned by the state machine, which stops the node.
+    type Error: Into<Box<dyn error::Error>>;
+
+    /// This specifies how the state machine should be affected based on the sequence of messages,
+    /// sending its responses through the context.
+    fn apply(&mut self, messages: Vec<Message<T>>, ctx: &mut Context<T>)
+        -> Result<(), Self::Error>;
+
+    /// This is called once the node has been initialized,
+    /// before any messages are applied to the state machine.
+    fn on_init(&mut self, _node_id: &str, _node_ids: &[String]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// This is called whenever a topology message updates the node's neighbors,
+    /// before the topology message is acknowledged.
+    fn on_topology(&mut self, _neighbors: &[String]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// This returns whether an outbound message is a request that the state machine expects a reply to.
+    /// Replies to such requests are passed to `on_reply` instead of `apply`.
+    fn expects_reply(&self, _message: &Message<T>) -> bool {
+        false
+    }
+
+    /// This returns the priority of an inbound or outbound message,
+    /// so that control messages are handled ahead of bulk data.
+    fn priority(&self, _message: &Message<T>) -> Priority {
+        Priority::Normal
+    }
+
+    /// This is called with a request and its reply when the reply to a request
+    /// that the state machine expects a reply to arrives, sending its responses through the context.
+    fn on_reply(
+        &mut self,
+        _request: Message<T>,
+        _reply: Message<T>,
+        _ctx: &mut Context<T>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// This is called with a request that the state machine expected a reply to
+    /// once it has been given up on without a reply, sending its responses through the context.
+    /// The request failed indefinitely, as it may have happened with only its replies lost.
+    fn on_abandoned(
+        &mut self,
+        _request: Message<T>,
+        _ctx: &mut Context<T>,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// This is called with the body of an admin message, such as a request to compact or to step down,
+    /// which the runtime routes here before parsing it as a workload message,
+    /// and returns the body of the reply, or `None` if the state machine does not handle it.
+    fn on_admin(&mut self, _request: &Value) -> Result<Option<Value>, Self::Error> {
+        Ok(None)
+    }
+
+    /// This returns the timers of the state machine, which the runtime fires while waiting for messages,
+    /// or `None` if the state machine does not use timers.
+    fn timers(&mut self) -> Option<&mut Timers<T>> {
+        None
+    }
+
+    /// The earliest time at which a timer of the state machine fires,
+    /// which is the next deadline of its timers unless it merges the timers of several state machines.
+    fn next_timer(&mut self) -> Option<Instant> {
+        self.timers().and_then(|timers| timers.next_deadline())
+    }
+
+    /// This returns the payloads of the timers of the state machine that fire by `now` with their deadlines,
+    /// in the order of their deadlines, which are those of its timers unless it merges the timers of several state machines.
+    fn poll_timers(&mut self, now: Instant) -> Vec<(Instant, T)>
+    where
+        T: Clone,
+    {
+        self.timers()
+            .map(|timers| timers.poll_deadlines(now))
+            .unwrap_or_default()
+    }
+
+    /// This returns a snapshot of the state for debugging,
+    /// which is `None` unless the state machine supports snapshots.
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        None
+    }
+}
+
+/// This allows a boxed state machine, such as one chosen at runtime, to be used as a state machine.
+impl<T, S> StateMachine<T> for Box<S>
+where
+    S: StateMachine<T> + ?Sized,
+{
+    type Error = S::Error;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<T>>,
+        ctx: &mut Context<T>,
+    ) -> Result<(), Self::Error> {
+        (**self).apply(messages, ctx)
+    }
+
+    fn on_init(&mut self, node_id: &str, node_ids: &[String]) -> Result<(), Self::Error> {
+        (**self).on_init(node_id, node_ids)
+    }
+
+    fn on_topology(&mut self, neighbors: &[String]) -> Result<(), Self::Error> {
+        (**self).on_topology(neighbors)
+    }
+
+    fn expects_reply(&self, message: &Message<T>) -> bool {
+        (**self).expects_reply(message)
+    }
+
+    fn priority(&self, message: &Message<T>) -> Priority {
+        (**self).priority(message)
+    }
+
+    fn on_reply(
+        &mut self,
+        request: Message<T>,
+        reply: Message<T>,
+        ctx: &mut Context<T>,
+    ) -> Result<(), Self::Error> {
+        (**self).on_reply(request, reply, ctx)
+    }
+
+    fn on_abandoned(
+        &mut self,
+        request: Message<T>,
+        ctx: &mut Context<T>,
+    ) -> Result<(), Self::Error> {
+        (**self).on_abandoned(request, ctx)
+    }
+
+    fn on_admin(&mut self, request: &Value) -> Result<Option<Value>, Self::Error> {
+        (**self).on_admin(request)
+    }
+
+    fn timers(&mut self) -> Option<&mut Timers<T>> {
+        (**self).timers()
+    }
+
+    fn next_timer(&mut self) -> Option<Instant> {
+        (**self).next_timer()
+    }
+
+    fn poll_timers(&mut self, now: Instant) -> Vec<(Instant, T)>
+    where
+        T: Clone,
+    {
+        (**self).poll_timers(now)
+    }
+
+    fn snapshot(&self) -> Option<serde_json::Value> {
+        (**self).snapshot()
+    }
+}
+
+/// This represents a state machine whose type is erased, e.g. to host several workloads in one node.
+pub type BoxedStateMachine<T> = Box<dyn StateMachine<T, Error = Box<dyn error::Error>>>;
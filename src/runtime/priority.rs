@@ -0,0 +1,11 @@
+/// The priority of a message, which decides the order messages are applied and written in.
+/// Control traffic, such as heartbeats and votes, is applied and written ahead of the rest
+/// and is not held back by a full window, so that it does not queue behind bulk data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Requests, replies and data, which are handled in the order they arrive.
+    #[default]
+    Normal,
+    /// The messages keeping a consensus or membership protocol alive.
+    Control,
+}
@@ -0,0 +1,128 @@
+use crate::{log, log::Level};
+use std::time::Duration;
+#[cfg(feature = "profiling")]
+use std::time::Instant;
+
+/// The time between the reports of a node's profile on stderr.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The stages of handling messages that are timed with the `profiling` feature.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Converting a message read from stdin into the workload's payload type.
+    Parse,
+    /// Applying messages and timers to the state machine.
+    Apply,
+    /// Converting, signing and writing a message to stdout.
+    Serialize,
+}
+
+impl Stage {
+    const ALL: [Stage; 3] = [Stage::Parse, Stage::Apply, Stage::Serialize];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Parse => "parse",
+            Stage::Apply => "apply",
+            Stage::Serialize => "serialize",
+        }
+    }
+}
+
+/// This times the stages of handling messages with the `profiling` feature,
+/// and periodically reports how many times each stage ran and how long it took in total on stderr,
+/// so that it is easy to tell whether JSON or the state machine dominates a slow workload.
+/// Without the feature this does nothing.
+/// Reading and tokenizing JSON on the reader thread is not included in the parse stage,
+/// as it cannot be told apart from waiting for input.
+#[cfg_attr(not(feature = "profiling"), derive(Default))]
+pub struct Profiler {
+    /// The number of runs and total time of each stage, indexed like `Stage::ALL`.
+    #[cfg(feature = "profiling")]
+    totals: [(u64, Duration); 3],
+    /// When the profile was last reported.
+    #[cfg(feature = "profiling")]
+    reported: Instant,
+}
+
+#[cfg(feature = "profiling")]
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            totals: [(0, Duration::ZERO); 3],
+            reported: Instant::now(),
+        }
+    }
+}
+
+impl Profiler {
+    /// This creates a profiler with nothing timed yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// This runs `f`, adding the time it takes to the stage.
+    #[inline]
+    pub fn time<R>(&mut self, stage: Stage, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "profiling")]
+        {
+            let started = Instant::now();
+            let result = f();
+            let index = Stage::ALL.iter().position(|s| *s == stage).unwrap_or(0);
+            let (runs, total) = &mut self.totals[index];
+            *runs += 1;
+            *total += started.elapsed();
+            result
+        }
+        #[cfg(not(feature = "profiling"))]
+        {
+            let _ = stage;
+            f()
+        }
+    }
+
+    /// The number of runs and total time of the stage.
+    pub fn total(&self, stage: Stage) -> (u64, Duration) {
+        #[cfg(feature = "profiling")]
+        {
+            let index = Stage::ALL.iter().position(|s| *s == stage).unwrap_or(0);
+            self.totals[index]
+        }
+        #[cfg(not(feature = "profiling"))]
+        {
+            let _ = stage;
+            (0, Duration::ZERO)
+        }
+    }
+
+    /// This writes the profile to stderr.
+    pub fn report(&self) {
+        if cfg!(not(feature = "profiling")) {
+            return;
+        }
+        let stages: Vec<String> = Stage::ALL
+            .iter()
+            .map(|stage| {
+                let (runs, total) = self.total(*stage);
+                let mean = total.checked_div(runs as u32).unwrap_or_default();
+                format!(
+                    "{} {} runs {:?} (mean {:?})",
+                    stage.name(),
+                    runs,
+                    total,
+                    mean
+                )
+            })
+            .collect();
+        log!(Level::Info, "profile: {}", stages.join(", "));
+    }
+
+    /// This writes the profile to stderr if `REPORT_INTERVAL` has passed since it was last written.
+    pub fn report_if_due(&mut self) {
+        #[cfg(feature = "profiling")]
+        if self.reported.elapsed() >= REPORT_INTERVAL {
+            self.reported = Instant::now();
+            self.report();
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use crate::{
+    error::Error,
+    protocol::{Message, Payload},
+    runtime::{Node, StateMachine},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+};
+
+/// The ID of the node started by the REPL.
+const NODE_ID: &str = "n0";
+
+/// The ID of the client that the REPL's commands are sent from.
+const CLIENT_ID: &str = "c0";
+
+/// This runs a single node interactively for manual debugging.
+/// Each input line is either a raw JSON message or an abbreviated command,
+/// which is split on whitespace and expanded by `expand` into a payload.
+/// The `topology <neighbors...>` command is built in and sets the neighbors of the node.
+/// Inbound and outbound messages are pretty-printed to the writer.
+pub fn run<T, S>(
+    state_machine: impl FnOnce(&str) -> S,
+    expand: impl Fn(&[&str]) -> Option<T>,
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+) -> Result<(), Error>
+where
+    T: Clone + Serialize + DeserializeOwned,
+    S: StateMachine<T>,
+{
+    let mut msg_id = 0;
+    let mut init = Message::new(
+        CLIENT_ID,
+        NODE_ID,
+        Payload::Init {
+            node_id: NODE_ID.to_string(),
+            node_ids: vec![NODE_ID.to_string()],
+        },
+    );
+    init.body.msg_id = Some(msg_id);
+    print(writer, "<-", &init)?;
+    let (mut node, resp) = Node::init(init, state_machine)?;
+    print(writer, "->", &resp)?;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        msg_id += 1;
+        let message = if line.starts_with('{') {
+            match line.parse::<Message<T>>() {
+                Ok(message) => message,
+                Err(e) => {
+                    writeln!(writer, "invalid message: {}", e)?;
+                    continue;
+                }
+            }
+        } else {
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            let body = match words.as_slice() {
+                ["topology", neighbors @ ..] => Some(Payload::Topology {
+                    topology: BTreeMap::from([(
+                        node.id().to_string(),
+                        neighbors.iter().map(|n| n.to_string()).collect(),
+                    )]),
+                }),
+                words => expand(words).map(Payload::Custom),
+            };
+            match body {
+                Some(payload) => {
+                    let mut message = Message::new(CLIENT_ID, node.id(), payload);
+                    message.body.msg_id = Some(msg_id);
+                    message
+                }
+                None => {
+                    writeln!(writer, "unknown command: {}", line)?;
+                    continue;
+                }
+            }
+        };
+        print(writer, "<-", &message)?;
+        for res in node.recv_messages(vec![message])? {
+            print(writer, "->", &res)?;
+        }
+    }
+    Ok(())
+}
+
+fn print<T>(writer: &mut impl Write, direction: &str, message: &Message<T>) -> Result<(), Error>
+where
+    T: Serialize,
+{
+    writeln!(
+        writer,
+        "{} {}",
+        direction,
+        serde_json::to_string_pretty(message)?
+    )?;
+    Ok(())
+}
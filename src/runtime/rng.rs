@@ -0,0 +1,53 @@
+use crate::runtime::clock;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// This is a small xorshift generator for the random choices of workloads, e.g. election timeouts,
+/// which is seeded from the seed of deterministic mode so that the same choices are made on every run.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// This creates a generator from a seed.
+    pub fn new(seed: u64) -> Self {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        Self {
+            state: hasher.finish() | 1,
+        }
+    }
+
+    /// This creates a generator for a node, which is seeded from the node's ID and the seed of deterministic mode,
+    /// or from the node's ID and the current time outside of it, so that nodes make different choices.
+    pub fn for_node(node_id: &str) -> Self {
+        let mut hasher = DefaultHasher::new();
+        node_id.hash(&mut hasher);
+        match clock::seed() {
+            Some(seed) => seed.hash(&mut hasher),
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos())
+                .unwrap_or_default()
+                .hash(&mut hasher),
+        }
+        Self::new(hasher.finish())
+    }
+
+    /// The next random number.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A random number below `n`, which is `0` if `n` is `0`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64().checked_rem(n).unwrap_or_default()
+    }
+}
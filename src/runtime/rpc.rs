@@ -0,0 +1,398 @@
+use crate::{log, log::Level, protocol::Message, runtime::RttEstimator};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+/// This configures how requests awaiting a reply are retransmitted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RetryPolicy {
+    /// The number of times a request is retransmitted before it is given up on.
+    pub limit: u32,
+    /// The time to wait for a reply before the first retransmission,
+    /// or `None` to derive it from the round-trip times to the peer.
+    /// Every retransmission doubles the time to wait.
+    pub timeout: Option<Duration>,
+}
+
+impl RetryPolicy {
+    /// This creates a policy retransmitting a request at most `limit` times
+    /// with timeouts derived from the round-trip times to the peer.
+    pub fn new(limit: u32) -> Self {
+        Self {
+            limit,
+            timeout: None,
+        }
+    }
+
+    /// This sets a fixed time to wait for a reply before the first retransmission.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+
+/// A request awaiting a reply.
+struct Pending<T> {
+    request: Message<T>,
+    /// The number of times the request has been retransmitted.
+    attempts: u32,
+    /// When the request is retransmitted or given up on if no reply has arrived.
+    deadline: Instant,
+}
+
+/// This represents the requests that have timed out, as returned by `Rpc::poll`.
+pub struct Expired<T> {
+    /// The requests to send again.
+    pub retransmit: Vec<Message<T>>,
+    /// The requests that were given up on after reaching the retry limit,
+    /// which failed indefinitely as they may or may not have happened.
+    pub abandoned: Vec<Message<T>>,
+}
+
+/// This represents the flow control counters of a peer, as returned by `Rpc::window_stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct WindowStats {
+    /// The requests to the peer awaiting a reply.
+    pub in_flight: usize,
+    /// The requests to the peer waiting for room in the window.
+    pub queued: usize,
+    /// The number of requests to the peer that have had to wait for room in the window.
+    pub stalled: u64,
+}
+
+/// This tracks the requests that a node is waiting on replies to,
+/// so that a reply can be correlated with its request by its `in_reply_to`,
+/// and retransmits the requests whose replies do not arrive in time.
+/// With a window set, at most that many requests to each peer await a reply at a time,
+/// and the rest wait in a queue until replies arrive, so that a slow peer is not overwhelmed.
+pub struct Rpc<T> {
+    /// The requests awaiting a reply keyed by their msg_id.
+    pending: HashMap<usize, Pending<T>>,
+    policy: RetryPolicy,
+    rtt: RttEstimator,
+    /// The maximum number of requests to each peer awaiting a reply, or `None` for no limit.
+    window: Option<usize>,
+    /// The requests to each peer waiting for room in the window, in the order they were made.
+    queued: HashMap<String, VecDeque<Message<T>>>,
+    /// The number of requests to each peer that have had to wait for room in the window.
+    stalled: HashMap<String, u64>,
+}
+
+impl<T> Default for Rpc<T> {
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+impl<T> Rpc<T> {
+    /// This creates a tracker without any pending requests.
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            pending: HashMap::new(),
+            policy,
+            rtt: RttEstimator::default(),
+            window: None,
+            queued: HashMap::new(),
+            stalled: HashMap::new(),
+        }
+    }
+
+    /// The policy for retransmitting requests.
+    pub fn policy(&self) -> RetryPolicy {
+        self.policy
+    }
+
+    /// This sets the policy for retransmitting requests,
+    /// which applies to the requests registered after it is set.
+    pub fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = policy;
+    }
+
+    /// The maximum number of requests to each peer awaiting a reply, or `None` for no limit.
+    pub fn window(&self) -> Option<usize> {
+        self.window
+    }
+
+    /// This sets the maximum number of requests to each peer awaiting a reply.
+    pub fn set_window(&mut self, window: Option<usize>) {
+        self.window = window;
+    }
+
+    /// The number of requests to the peer awaiting a reply.
+    pub fn in_flight(&self, peer: &str) -> usize {
+        self.pending
+            .values()
+            .filter(|pending| pending.request.dest == peer)
+            .count()
+    }
+
+    /// Whether a request to the peer can be sent without waiting for room in the window.
+    fn is_open(&self, peer: &str) -> bool {
+        self.queued.get(peer).is_none_or(VecDeque::is_empty)
+            && self
+                .window
+                .is_none_or(|window| self.in_flight(peer) < window)
+    }
+
+    /// The flow control counters of every peer that requests have been made to.
+    pub fn window_stats(&self) -> BTreeMap<String, WindowStats> {
+        let mut stats: BTreeMap<String, WindowStats> = BTreeMap::new();
+        for pending in self.pending.values() {
+            stats
+                .entry(pending.request.dest.clone())
+                .or_default()
+                .in_flight += 1;
+        }
+        for (peer, queued) in &self.queued {
+            stats.entry(peer.clone()).or_default().queued = queued.len();
+        }
+        for (peer, stalled) in &self.stalled {
+            stats.entry(peer.clone()).or_default().stalled = *stalled;
+        }
+        stats
+    }
+
+    /// This registers a request made at `now` if there is room in the window for its peer,
+    /// returning whether it should be sent now.
+    /// Otherwise the request waits in a queue and is returned by `release` once there is room.
+    pub fn admit(&mut self, request: Message<T>, now: Instant) -> bool {
+        if self.is_open(&request.dest) {
+            self.call(request, now);
+            return true;
+        }
+        *self.stalled.entry(request.dest.clone()).or_default() += 1;
+        if !self.queued.contains_key(&request.dest) {
+            log!(
+                Level::Info,
+                "window to {} is full, holding back requests",
+                request.dest
+            );
+        }
+        self.queued
+            .entry(request.dest.clone())
+            .or_default()
+            .push_back(request);
+        false
+    }
+
+    /// The round-trip times of the requests that have been replied to.
+    pub fn rtt(&self) -> &RttEstimator {
+        &self.rtt
+    }
+
+    /// This returns how long to wait for a reply from the peer after `attempts` retransmissions.
+    fn wait(&self, peer: &str, attempts: u32) -> Duration {
+        match self.policy.timeout {
+            Some(timeout) => timeout.saturating_mul(2u32.saturating_pow(attempts)),
+            None => self.rtt.backoff(peer, attempts),
+        }
+    }
+
+    /// This registers a request sent at `now` as awaiting a reply.
+    /// Requests without a msg_id cannot be replied to and are not registered.
+    pub fn call(&mut self, request: Message<T>, now: Instant) {
+        let Some(msg_id) = request.body.msg_id else {
+            return;
+        };
+        self.rtt.sent(&request.dest, msg_id, now);
+        let deadline = now + self.wait(&request.dest, 0);
+        self.pending.insert(
+            msg_id,
+            Pending {
+                request,
+                attempts: 0,
+                deadline,
+            },
+        );
+    }
+
+    /// This returns the request that a message received at `now` replies to and stops waiting on it,
+    /// or `None` if the message is not a reply to a pending request.
+    /// A reply must come from the node that the request was sent to.
+    pub fn resolve(&mut self, reply: &Message<T>, now: Instant) -> Option<Message<T>> {
+        let msg_id = reply.body.in_reply_to?;
+        match self.pending.get(&msg_id) {
+            Some(pending) if pending.request.dest == reply.src => {
+                self.rtt.replied(&reply.src, msg_id, now);
+                self.pending.remove(&msg_id).map(|pending| pending.request)
+            }
+            _ => None,
+        }
+    }
+
+    /// This stops waiting on a reply to a request, returning the request if it was pending.
+    pub fn cancel(&mut self, msg_id: usize) -> Option<Message<T>> {
+        let pending = self.pending.remove(&msg_id)?;
+        self.rtt.abandon(&pending.request.dest, msg_id);
+        Some(pending.request)
+    }
+
+    /// The earliest time at which a pending request times out.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.deadline).min()
+    }
+
+    /// The requests awaiting a reply.
+    pub fn pending(&self) -> impl Iterator<Item = &Message<T>> {
+        self.pending.values().map(|pending| &pending.request)
+    }
+
+    /// The number of requests awaiting a reply.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether no requests are awaiting a reply.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl<T> Rpc<T>
+where
+    T: Clone,
+{
+    /// This registers the queued requests that there is now room for in the window of their peer
+    /// as sent at `now`, returning them in the order they were made.
+    pub fn release(&mut self, now: Instant) -> Vec<Message<T>> {
+        let mut released = Vec::new();
+        let peers: Vec<String> = self.queued.keys().cloned().collect();
+        for peer in peers {
+            while self
+                .window
+                .is_none_or(|window| self.in_flight(&peer) < window)
+            {
+                let Some(request) = self.queued.get_mut(&peer).and_then(VecDeque::pop_front) else {
+                    break;
+                };
+                self.call(request.clone(), now);
+                released.push(request);
+            }
+        }
+        self.queued.retain(|_, queued| !queued.is_empty());
+        released.sort_by_key(|request| request.body.msg_id);
+        released
+    }
+
+    /// This returns the requests that have timed out by `now`,
+    /// keeping those under the retry limit pending with the next timeout
+    /// and giving up on the rest.
+    /// Retransmitted requests keep their msg_id so that a late reply to any attempt resolves them,
+    /// but are no longer used to measure round trips as the reply could be to either attempt.
+    pub fn poll(&mut self, now: Instant) -> Expired<T> {
+        let mut expired = Expired {
+            retransmit: Vec::new(),
+            abandoned: Vec::new(),
+        };
+        let mut timed_out: Vec<usize> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(msg_id, _)| *msg_id)
+            .collect();
+        timed_out.sort_unstable();
+        for msg_id in timed_out {
+            let Some(pending) = self.pending.get(&msg_id) else {
+                continue;
+            };
+            let dest = pending.request.dest.clone();
+            let attempts = pending.attempts;
+            self.rtt.abandon(&dest, msg_id);
+            if attempts >= self.policy.limit {
+                if let Some(pending) = self.pending.remove(&msg_id) {
+                    expired.abandoned.push(pending.request);
+                }
+                continue;
+            }
+            let deadline = now + self.wait(&dest, attempts + 1);
+            if let Some(pending) = self.pending.get_mut(&msg_id) {
+                pending.attempts += 1;
+                pending.deadline = deadline;
+                expired.retransmit.push(pending.request.clone());
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn request(dest: &str, msg_id: usize) -> Message<Value> {
+        let mut request = Message::new("n1", dest, json!({"type": "gossip"}));
+        request.body.msg_id = Some(msg_id);
+        request
+    }
+
+    fn reply(src: &str, in_reply_to: usize) -> Message<Value> {
+        let mut reply = Message::new(src, "n1", json!({"type": "gossip_ok"}));
+        reply.body.in_reply_to = Some(in_reply_to);
+        reply
+    }
+
+    fn msg_ids(messages: &[Message<Value>]) -> Vec<usize> {
+        messages.iter().filter_map(|m| m.body.msg_id).collect()
+    }
+
+    #[test]
+    fn replies_resolve_requests_only_from_their_peer() {
+        let now = Instant::now();
+        let mut rpc = Rpc::default();
+        rpc.call(request("n2", 1), now);
+        assert!(rpc.resolve(&reply("n3", 1), now).is_none());
+        assert!(rpc.resolve(&reply("n2", 2), now).is_none());
+        assert_eq!(
+            rpc.resolve(&reply("n2", 1), now).unwrap().body.msg_id,
+            Some(1)
+        );
+        assert!(rpc.resolve(&reply("n2", 1), now).is_none());
+        assert!(rpc.is_empty());
+    }
+
+    #[test]
+    fn timed_out_requests_back_off_then_are_abandoned() {
+        let now = Instant::now();
+        let timeout = Duration::from_millis(100);
+        let mut rpc = Rpc::new(RetryPolicy::new(2).with_timeout(timeout));
+        rpc.call(request("n2", 1), now);
+        assert_eq!(rpc.next_deadline(), Some(now + timeout));
+        assert!(rpc.poll(now + timeout / 2).retransmit.is_empty());
+        assert_eq!(msg_ids(&rpc.poll(now + timeout).retransmit), vec![1]);
+        assert_eq!(rpc.next_deadline(), Some(now + timeout * 3));
+        assert_eq!(msg_ids(&rpc.poll(now + timeout * 3).retransmit), vec![1]);
+        let expired = rpc.poll(now + timeout * 7);
+        assert!(expired.retransmit.is_empty());
+        assert_eq!(msg_ids(&expired.abandoned), vec![1]);
+        assert!(rpc.is_empty());
+    }
+
+    #[test]
+    fn a_full_window_holds_requests_back_until_replies_arrive() {
+        let now = Instant::now();
+        let mut rpc = Rpc::default();
+        rpc.set_window(Some(1));
+        assert!(rpc.admit(request("n2", 1), now));
+        assert!(!rpc.admit(request("n2", 2), now));
+        assert!(!rpc.admit(request("n2", 3), now));
+        assert!(rpc.admit(request("n3", 4), now));
+        assert!(rpc.release(now).is_empty());
+        assert_eq!(
+            rpc.window_stats()["n2"],
+            WindowStats {
+                in_flight: 1,
+                queued: 2,
+                stalled: 2,
+            }
+        );
+        rpc.resolve(&reply("n2", 1), now);
+        assert_eq!(msg_ids(&rpc.release(now)), vec![2]);
+        assert!(rpc.cancel(2).is_some());
+        assert_eq!(msg_ids(&rpc.release(now)), vec![3]);
+        assert_eq!(rpc.in_flight("n2"), 1);
+        assert_eq!(rpc.window_stats()["n2"].queued, 0);
+    }
+}
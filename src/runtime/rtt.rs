@@ -0,0 +1,150 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// The timeout for a peer before any of its round trips have been measured.
+pub const INITIAL_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// The weight of a new sample in the smoothed round-trip time, as in TCP.
+const RTT_ALPHA: f64 = 1.0 / 8.0;
+
+/// The weight of a new sample in the round-trip time variation, as in TCP.
+const RTTVAR_BETA: f64 = 1.0 / 4.0;
+
+/// This represents an exponentially weighted moving average,
+/// where each sample moves the average towards it by the fraction `alpha`.
+#[derive(Clone, Copy, Debug)]
+pub struct Ewma {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl Ewma {
+    /// This creates an average without samples, with `alpha` between 0 and 1.
+    pub fn new(alpha: f64) -> Self {
+        Self {
+            alpha: alpha.clamp(0.0, 1.0),
+            value: None,
+        }
+    }
+
+    /// This adds a sample, returning the new average.
+    /// The first sample becomes the average.
+    pub fn update(&mut self, sample: f64) -> f64 {
+        let value = match self.value {
+            Some(value) => value + self.alpha * (sample - value),
+            None => sample,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    /// The average, or `None` before the first sample.
+    pub fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+/// The round-trip estimates of a single peer.
+#[derive(Clone, Copy, Debug)]
+struct Estimate {
+    /// The smoothed round-trip time in seconds.
+    srtt: Ewma,
+    /// The smoothed deviation of the samples from `srtt` in seconds.
+    rttvar: Ewma,
+}
+
+/// This estimates the round-trip times of RPCs to each peer and derives timeouts from them,
+/// in the style of TCP's retransmission timer (RFC 6298),
+/// so that slow links get longer timeouts without slowing down retries on fast ones.
+#[derive(Debug)]
+pub struct RttEstimator {
+    estimates: HashMap<String, Estimate>,
+    /// The send times of the requests awaiting replies, keyed by peer and msg_id.
+    in_flight: HashMap<(String, usize), Instant>,
+    min_timeout: Duration,
+    max_timeout: Duration,
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(10), Duration::from_secs(10))
+    }
+}
+
+impl RttEstimator {
+    /// This creates an estimator whose timeouts stay between `min_timeout` and `max_timeout`.
+    pub fn new(min_timeout: Duration, max_timeout: Duration) -> Self {
+        Self {
+            estimates: HashMap::new(),
+            in_flight: HashMap::new(),
+            min_timeout,
+            max_timeout: max_timeout.max(min_timeout),
+        }
+    }
+
+    /// This records a measured round trip to the peer.
+    pub fn observe(&mut self, peer: &str, rtt: Duration) {
+        let sample = rtt.as_secs_f64();
+        let estimate = self
+            .estimates
+            .entry(peer.to_string())
+            .or_insert_with(|| Estimate {
+                srtt: Ewma::new(RTT_ALPHA),
+                rttvar: Ewma::new(RTTVAR_BETA),
+            });
+        match estimate.srtt.value() {
+            Some(srtt) => estimate.rttvar.update((srtt - sample).abs()),
+            None => estimate.rttvar.update(sample / 2.0),
+        };
+        estimate.srtt.update(sample);
+    }
+
+    /// This records that a request with the msg_id was sent to the peer at `now`.
+    pub fn sent(&mut self, peer: &str, msg_id: usize, now: Instant) {
+        self.in_flight.insert((peer.to_string(), msg_id), now);
+    }
+
+    /// This records the reply from the peer to the msg_id at `now`, returning the round trip
+    /// if the request was recorded with `sent`.
+    pub fn replied(&mut self, peer: &str, in_reply_to: usize, now: Instant) -> Option<Duration> {
+        let sent = self.in_flight.remove(&(peer.to_string(), in_reply_to))?;
+        let rtt = now.saturating_duration_since(sent);
+        self.observe(peer, rtt);
+        Some(rtt)
+    }
+
+    /// This forgets a request that will not get a reply, e.g. because it was given up on.
+    pub fn abandon(&mut self, peer: &str, msg_id: usize) {
+        self.in_flight.remove(&(peer.to_string(), msg_id));
+    }
+
+    /// The smoothed round-trip time to the peer, if any round trips have been measured.
+    pub fn srtt(&self, peer: &str) -> Option<Duration> {
+        self.estimates
+            .get(peer)
+            .and_then(|e| e.srtt.value())
+            .map(Duration::from_secs_f64)
+    }
+
+    /// The time to wait for a reply from the peer before retrying,
+    /// which is the smoothed round-trip time plus four times its variation.
+    pub fn timeout(&self, peer: &str) -> Duration {
+        let timeout = match self.estimates.get(peer) {
+            Some(Estimate { srtt, rttvar }) => Duration::from_secs_f64(
+                srtt.value().unwrap_or(0.0) + 4.0 * rttvar.value().unwrap_or(0.0),
+            ),
+            None => INITIAL_TIMEOUT,
+        };
+        timeout.clamp(self.min_timeout, self.max_timeout)
+    }
+
+    /// The time to wait before the retry after `attempt` earlier attempts,
+    /// doubling the peer's timeout with every attempt.
+    pub fn backoff(&self, peer: &str, attempt: u32) -> Duration {
+        self.timeout(peer)
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_timeout)
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{
+    log,
+    log::Level,
+    protocol::{Message, Payload},
+    runtime::clock,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    env,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The environment variable that enables sequence numbers when set to anything but `0`.
+pub const SEQUENCE_ENV: &str = "VORTEX_SEQUENCE";
+
+/// The field of a message body holding its sequence number.
+pub const SEQ_FIELD: &str = "seq";
+
+/// The field of a message body holding the epoch of the sender,
+/// which is the time it started so that the sequence numbers of a restarted peer are told apart from those before it.
+pub const EPOCH_FIELD: &str = "epoch";
+
+/// The type of the message asking a peer to resend the messages with the given sequence numbers.
+pub const RESEND_TYPE: &str = "seq_resend";
+
+/// The number of messages to each peer that are kept for resending,
+/// which is also the largest gap that is asked to be resent.
+const SENT_BUFFER: usize = 1024;
+
+/// What was received from a peer.
+#[derive(Default)]
+struct Stream {
+    /// The epoch of the peer the sequence numbers are from.
+    epoch: u64,
+    /// The highest sequence number received.
+    highest: u64,
+    /// The sequence numbers below the highest one that have not been received.
+    missing: BTreeSet<u64>,
+}
+
+impl Stream {
+    /// This records a sequence number from an epoch of the peer, returning whether its message should be delivered
+    /// along with the sequence numbers that were found to be missing.
+    /// A later epoch means the peer restarted and its sequence numbers start again,
+    /// and messages from an earlier epoch are stale and dropped.
+    fn accept(&mut self, epoch: u64, seq: u64) -> (bool, Vec<u64>) {
+        if epoch < self.epoch {
+            return (false, Vec::new());
+        }
+        if epoch > self.epoch {
+            *self = Self {
+                epoch,
+                ..Self::default()
+            };
+        }
+        if seq <= self.highest {
+            return (self.missing.remove(&seq), Vec::new());
+        }
+        let gap: Vec<u64> = if seq - self.highest > SENT_BUFFER as u64 {
+            self.missing.clear();
+            Vec::new()
+        } else {
+            (self.highest + 1..seq).collect()
+        };
+        self.missing.extend(&gap);
+        self.highest = seq;
+        let oldest = seq.saturating_sub(SENT_BUFFER as u64);
+        self.missing = self.missing.split_off(&oldest);
+        (true, gap)
+    }
+}
+
+/// This stamps inter-node messages with a sequence number per destination in a `seq` field of their body,
+/// and detects gaps in the sequence numbers received from each peer,
+/// asking the peer to resend the missing messages instead of losing them silently.
+/// Messages that were already received are dropped, and the most recent messages to each peer
+/// are kept so that they can be resent.
+/// Messages to and from clients and services are left untouched,
+/// and so are error, init and topology messages, as they are parsed without their extra fields.
+pub struct Sequencer {
+    /// The ID of the node, which resend requests are sent from.
+    node_id: String,
+    /// The epoch of the node, which is sent with every sequence number.
+    epoch: u64,
+    /// The nodes in the cluster, which are the only ones whose messages are sequenced.
+    peers: HashSet<String>,
+    /// The sequence number of the last message to each peer.
+    next: HashMap<String, u64>,
+    /// The most recent messages to each peer by sequence number.
+    sent: HashMap<String, VecDeque<(u64, Message<Value>)>>,
+    /// What was received from each peer.
+    received: HashMap<String, Stream>,
+    /// Whether messages are sequenced at all.
+    enabled: bool,
+}
+
+impl Sequencer {
+    /// This creates a sequencer for the node's messages to and from its peers.
+    pub fn new(node_id: &str, peers: &[String]) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            epoch: epoch(),
+            peers: peers.iter().cloned().collect(),
+            next: HashMap::new(),
+            sent: HashMap::new(),
+            received: HashMap::new(),
+            enabled: true,
+        }
+    }
+
+    /// This creates a sequencer that leaves every message untouched.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new("", &[])
+        }
+    }
+
+    /// This creates a sequencer if `VORTEX_SEQUENCE` is set, and a disabled one otherwise.
+    pub fn from_env(node_id: &str, peers: &[String]) -> Self {
+        match env::var(SEQUENCE_ENV) {
+            Ok(v) if v != "0" => Self::new(node_id, peers),
+            _ => Self::disabled(),
+        }
+    }
+
+    /// Whether messages are sequenced at all.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether messages between the node and `peer` are sequenced.
+    fn sequenced(&self, peer: &str) -> bool {
+        self.enabled && peer != self.node_id && self.peers.contains(peer)
+    }
+
+    /// This converts the message to JSON, adding the next sequence number to its destination
+    /// if it is a custom message to a peer.
+    pub fn stamp<T>(&mut self, message: &Message<T>) -> Result<Message<Value>, serde_json::Error>
+    where
+        T: Serialize,
+    {
+        let mut value = serde_json::to_value(message)?;
+        let custom = matches!(message.body.payload, Payload::Custom(_));
+        if !custom || !self.sequenced(&message.dest) {
+            return serde_json::from_value(value);
+        }
+        let next = self.next.entry(message.dest.clone()).or_default();
+        *next += 1;
+        let seq = *next;
+        if let Some(body) = value["body"].as_object_mut() {
+            body.insert(SEQ_FIELD.to_string(), seq.into());
+            body.insert(EPOCH_FIELD.to_string(), self.epoch.into());
+        }
+        let message: Message<Value> = serde_json::from_value(value)?;
+        let sent = self.sent.entry(message.dest.clone()).or_default();
+        if sent.len() == SENT_BUFFER {
+            sent.pop_front();
+        }
+        sent.push_back((seq, message.clone()));
+        Ok(message)
+    }
+
+    /// This checks the sequence number of a message from a peer,
+    /// returning the message unless it was already received,
+    /// along with the messages to send in response:
+    /// a resend request for any gap the message reveals,
+    /// or the resent messages if the message is itself a resend request.
+    pub fn accept(
+        &mut self,
+        mut message: Message<Value>,
+    ) -> (Option<Message<Value>>, Vec<Message<Value>>) {
+        if !self.sequenced(&message.src) {
+            return (Some(message), Vec::new());
+        }
+        let Payload::Custom(body) = &mut message.body.payload else {
+            return (Some(message), Vec::new());
+        };
+        if body["type"] == RESEND_TYPE {
+            if body[EPOCH_FIELD].as_u64() != Some(self.epoch) {
+                return (None, Vec::new());
+            }
+            return (None, self.resend(&message.src, &body["seqs"]));
+        }
+        let Some(fields) = body.as_object_mut() else {
+            return (Some(message), Vec::new());
+        };
+        let epoch = fields
+            .remove(EPOCH_FIELD)
+            .and_then(|epoch| epoch.as_u64())
+            .unwrap_or_default();
+        let Some(seq) = fields.remove(SEQ_FIELD).and_then(|seq| seq.as_u64()) else {
+            return (Some(message), Vec::new());
+        };
+        let stream = self.received.entry(message.src.clone()).or_default();
+        let (deliver, gap) = stream.accept(epoch, seq);
+        if epoch < stream.epoch {
+            log!(
+                Level::Debug,
+                "dropping stale message from {} before it restarted",
+                message.src
+            );
+        }
+        let mut responses = Vec::new();
+        if !gap.is_empty() {
+            log!(
+                Level::Warn,
+                "seq gap from {}: asking to resend {} messages",
+                message.src,
+                gap.len()
+            );
+            responses.push(Message::new(
+                &self.node_id,
+                &message.src,
+                json!({ "type": RESEND_TYPE, EPOCH_FIELD: stream.epoch, "seqs": gap }),
+            ));
+        }
+        (deliver.then_some(message), responses)
+    }
+
+    /// This returns the messages to the peer with the requested sequence numbers that are still kept.
+    fn resend(&self, peer: &str, seqs: &Value) -> Vec<Message<Value>> {
+        let seqs: HashSet<u64> = seqs
+            .as_array()
+            .map(|seqs| seqs.iter().filter_map(Value::as_u64).collect())
+            .unwrap_or_default();
+        self.sent
+            .get(peer)
+            .map(|sent| {
+                sent.iter()
+                    .filter(|(seq, _)| seqs.contains(seq))
+                    .map(|(_, message)| message.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// The epoch of a node starting now, which is the time in milliseconds since the Unix epoch,
+/// or `1` in deterministic mode so that the output does not depend on when the node started.
+fn epoch() -> u64 {
+    if clock::seed().is_some() {
+        return 1;
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ErrorCode;
+
+    fn peers() -> Vec<String> {
+        vec!["n1".to_string(), "n2".to_string()]
+    }
+
+    fn gossip(n: u64) -> Message<Value> {
+        Message::new("n1", "n2", json!({"type": "gossip", "n": n}))
+    }
+
+    /// This stamps the messages from n1 to n2.
+    fn stamp(n1: &mut Sequencer, count: u64) -> Vec<Message<Value>> {
+        (1..=count).map(|n| n1.stamp(&gossip(n)).unwrap()).collect()
+    }
+
+    fn seqs(resend: &Message<Value>) -> Vec<u64> {
+        let Payload::Custom(body) = &resend.body.payload else {
+            panic!("not a resend request");
+        };
+        serde_json::from_value(body["seqs"].clone()).unwrap()
+    }
+
+    #[test]
+    fn in_order_messages_are_delivered_without_seq() {
+        let (mut n1, mut n2) = (
+            Sequencer::new("n1", &peers()),
+            Sequencer::new("n2", &peers()),
+        );
+        for message in stamp(&mut n1, 3) {
+            let (delivered, responses) = n2.accept(message);
+            let Payload::Custom(body) = delivered.unwrap().body.payload else {
+                panic!("not delivered");
+            };
+            assert!(body.get(SEQ_FIELD).is_none() && body.get(EPOCH_FIELD).is_none());
+            assert!(responses.is_empty());
+        }
+    }
+
+    #[test]
+    fn gaps_are_resent_and_duplicates_dropped() {
+        let (mut n1, mut n2) = (
+            Sequencer::new("n1", &peers()),
+            Sequencer::new("n2", &peers()),
+        );
+        let sent = stamp(&mut n1, 4);
+        n2.accept(sent[0].clone());
+        let (delivered, responses) = n2.accept(sent[3].clone());
+        assert!(delivered.is_some());
+        let [resend] = <[_; 1]>::try_from(responses).unwrap();
+        assert_eq!(seqs(&resend), [2, 3]);
+        let (none, resent) = n1.accept(Message {
+            src: "n2".into(),
+            dest: "n1".into(),
+            body: resend.body,
+        });
+        assert!(none.is_none());
+        assert_eq!(resent.len(), 2);
+        for message in resent {
+            assert!(n2.accept(message).0.is_some());
+        }
+        assert!(n2.accept(sent[1].clone()).0.is_none());
+    }
+
+    #[test]
+    fn restarts_start_over_and_stale_messages_are_dropped() {
+        let (mut n1, mut n2) = (
+            Sequencer::new("n1", &peers()),
+            Sequencer::new("n2", &peers()),
+        );
+        let before = stamp(&mut n1, 3);
+        n2.accept(before[0].clone());
+        n2.accept(before[1].clone());
+        let mut restarted = Sequencer::new("n1", &peers());
+        restarted.epoch = n1.epoch + 1;
+        let after = stamp(&mut restarted, 1);
+        let (delivered, responses) = n2.accept(after[0].clone());
+        assert!(delivered.is_some() && responses.is_empty());
+        let (stale, responses) = n2.accept(before[2].clone());
+        assert!(stale.is_none() && responses.is_empty());
+    }
+
+    #[test]
+    fn resend_requests_from_another_epoch_are_ignored() {
+        let mut n1 = Sequencer::new("n1", &peers());
+        stamp(&mut n1, 2);
+        let request = Message::new(
+            "n2",
+            "n1",
+            json!({ "type": RESEND_TYPE, EPOCH_FIELD: n1.epoch + 1, "seqs": [1] }),
+        );
+        assert!(n1.accept(request).1.is_empty());
+    }
+
+    #[test]
+    fn error_replies_do_not_take_a_seq() {
+        let (mut n1, mut n2) = (
+            Sequencer::new("n1", &peers()),
+            Sequencer::new("n2", &peers()),
+        );
+        let mut request = gossip(0);
+        request.body.msg_id = Some(1);
+        let error = Message {
+            src: "n1".into(),
+            dest: "n2".into(),
+            body: request.error_reply(ErrorCode::Abort, "").body,
+        };
+        let error = n1.stamp(&error).unwrap();
+        assert!(n2.accept(error).0.is_some());
+        let (delivered, responses) = n2.accept(stamp(&mut n1, 1).remove(0));
+        assert!(delivered.is_some() && responses.is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{
+    error::Error,
+    runtime::{Node, StateMachine},
+};
+use std::{
+    env, fs,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The environment variable holding the directory state snapshots are dumped to,
+/// which defaults to the system's temporary directory.
+pub const SNAPSHOT_DIR_ENV: &str = "VORTEX_SNAPSHOT_DIR";
+
+/// Whether a snapshot has been requested since the last dump.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(all(feature = "unix", unix))]
+mod ffi {
+    use std::os::raw::c_int;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub const SIGUSR1: c_int = 10;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub const SIGUSR1: c_int = 30;
+
+    /// The value `signal` returns on failure.
+    pub const SIG_ERR: usize = usize::MAX;
+
+    extern "C" {
+        pub fn signal(signum: c_int, handler: usize) -> usize;
+    }
+}
+
+#[cfg(all(feature = "unix", unix))]
+extern "C" fn on_sigusr1(_: std::os::raw::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// This installs a SIGUSR1 handler requesting a snapshot of the state machine.
+/// The handler only sets a flag, so the snapshot is taken at the next message boundary
+/// by `dump_if_requested` without interrupting message processing.
+/// Without the `unix` feature or on other platforms this does nothing.
+pub fn install() -> Result<(), Error> {
+    #[cfg(all(feature = "unix", unix))]
+    {
+        let handler = on_sigusr1 as extern "C" fn(std::os::raw::c_int) as usize;
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe.
+        if unsafe { ffi::signal(ffi::SIGUSR1, handler) } == ffi::SIG_ERR {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// This requests a snapshot as if SIGUSR1 had been received.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// This writes the node's state machine snapshot to a file if one has been requested,
+/// returning the path of the file.
+/// State machines that do not support snapshots are dumped as `null`.
+pub fn dump_if_requested<T, S>(node: &Node<T, S>) -> Result<Option<PathBuf>, Error>
+where
+    S: StateMachine<T>,
+{
+    if !REQUESTED.swap(false, Ordering::SeqCst) {
+        return Ok(None);
+    }
+    let dir = env::var_os(SNAPSHOT_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir);
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let path = dir.join(format!("vortex-{}-{}.json", node.id(), millis));
+    fs::write(&path, serde_json::to_string_pretty(&node.snapshot())?)?;
+    Ok(Some(path))
+}
@@ -0,0 +1,122 @@
+use crate::runtime::clock;
+use std::time::{Duration, Instant};
+
+/// This identifies a timer so that it can be cancelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TimerId(usize);
+
+/// A registered timer.
+struct Timer<T> {
+    id: TimerId,
+    /// When the timer fires next.
+    deadline: Instant,
+    /// The time between firings of a periodic timer, or `None` if it fires once.
+    period: Option<Duration>,
+    /// The payload delivered to the state machine when the timer fires.
+    payload: T,
+}
+
+/// This holds the timers of a state machine, which the runtime fires alongside incoming messages
+/// by delivering each timer's payload to the state machine as a message from the node to itself.
+pub struct Timers<T> {
+    timers: Vec<Timer<T>>,
+    /// The ID of the last timer registered.
+    id_counter: usize,
+}
+
+impl<T> Default for Timers<T> {
+    fn default() -> Self {
+        Self {
+            timers: Vec::new(),
+            id_counter: 0,
+        }
+    }
+}
+
+impl<T> Timers<T> {
+    /// This creates an empty set of timers.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn register(&mut self, deadline: Instant, period: Option<Duration>, payload: T) -> TimerId {
+        self.id_counter += 1;
+        let id = TimerId(self.id_counter);
+        self.timers.push(Timer {
+            id,
+            deadline,
+            period,
+            payload,
+        });
+        id
+    }
+
+    /// This registers a timer that delivers the payload once after `delay`.
+    pub fn after(&mut self, delay: Duration, payload: T) -> TimerId {
+        self.register(clock::now() + delay, None, payload)
+    }
+
+    /// This registers a timer that delivers the payload every `period`, starting one period from now.
+    pub fn every(&mut self, period: Duration, payload: T) -> TimerId {
+        self.register(clock::now() + period, Some(period), payload)
+    }
+
+    /// This cancels a timer, returning whether it was registered.
+    pub fn cancel(&mut self, id: TimerId) -> bool {
+        let len = self.timers.len();
+        self.timers.retain(|timer| timer.id != id);
+        self.timers.len() != len
+    }
+
+    /// The earliest time at which a timer fires.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.iter().map(|timer| timer.deadline).min()
+    }
+
+    /// The number of registered timers.
+    pub fn len(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// Whether no timers are registered.
+    pub fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+}
+
+impl<T> Timers<T>
+where
+    T: Clone,
+{
+    /// This returns the payloads of the timers that fire by `now` in the order of their deadlines.
+    /// One-off timers are removed once they fire, and periodic timers are rescheduled a period later,
+    /// or a period from `now` if they have fallen more than a period behind.
+    pub fn poll(&mut self, now: Instant) -> Vec<T> {
+        self.poll_deadlines(now)
+            .into_iter()
+            .map(|(_, payload)| payload)
+            .collect()
+    }
+
+    /// This returns the payloads of the timers that fire by `now` like `poll`, along with their deadlines,
+    /// e.g. to interleave them with the timers of other state machines.
+    pub fn poll_deadlines(&mut self, now: Instant) -> Vec<(Instant, T)> {
+        let mut fired: Vec<(Instant, T)> = Vec::new();
+        self.timers.retain_mut(|timer| {
+            if timer.deadline > now {
+                return true;
+            }
+            fired.push((timer.deadline, timer.payload.clone()));
+            let Some(period) = timer.period else {
+                return false;
+            };
+            timer.deadline += period;
+            if timer.deadline <= now {
+                timer.deadline = now + period;
+            }
+            true
+        });
+        fired.sort_by_key(|(deadline, _)| *deadline);
+        fired
+    }
+}
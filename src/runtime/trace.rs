@@ -0,0 +1,81 @@
+use crate::{error::Error, log, log::Level, protocol::Message};
+use serde::Serialize;
+use std::{collections::HashSet, env, io::Write};
+
+/// The environment variable that enables trace tags when set to anything but `0`.
+pub const TRACE_ENV: &str = "VORTEX_TRACE";
+
+/// This tags inter-node messages with a compact trace tag in a `trace` field of their body,
+/// and logs a line mapping each tag to the message on stderr.
+/// Since Maelstrom shows message bodies in its message timeline diagrams,
+/// the tags make it easy to find a logged message in the diagram and vice versa.
+/// Messages to and from clients and services are written untouched.
+pub struct Tracer {
+    /// The ID of the node, used as the prefix of its tags.
+    node_id: String,
+    /// The nodes in the cluster, which are the only destinations that get tagged.
+    peers: HashSet<String>,
+    /// The number of messages tagged so far.
+    counter: u64,
+    /// Whether messages are tagged at all.
+    enabled: bool,
+}
+
+impl Tracer {
+    /// This creates a tracer for the node that tags messages to its peers.
+    pub fn new(node_id: &str, peers: &[String]) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+            peers: peers.iter().cloned().collect(),
+            counter: 0,
+            enabled: true,
+        }
+    }
+
+    /// This creates a tracer that writes every message untouched.
+    pub fn disabled() -> Self {
+        Self {
+            node_id: String::new(),
+            peers: HashSet::new(),
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    /// This creates a tracer if `VORTEX_TRACE` is set, and a disabled one otherwise.
+    pub fn from_env(node_id: &str, peers: &[String]) -> Self {
+        match env::var(TRACE_ENV) {
+            Ok(v) if v != "0" => Self::new(node_id, peers),
+            _ => Self::disabled(),
+        }
+    }
+
+    /// This writes the message with a trailing newline, tagging it if it is to a peer.
+    pub fn write<T>(&mut self, message: &Message<T>, writer: &mut impl Write) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        if !self.enabled || !self.peers.contains(&message.dest) || message.dest == self.node_id {
+            return message.write(writer);
+        }
+        self.counter += 1;
+        let tag = format!("{}.{}", self.node_id, self.counter);
+        let mut value = serde_json::to_value(message)?;
+        let body = &mut value["body"];
+        log!(
+            Level::Info,
+            "trace {} = {} {} -> {} msg_id {}",
+            tag,
+            body["type"].as_str().unwrap_or("unknown"),
+            message.src,
+            message.dest,
+            body["msg_id"],
+        );
+        if let Some(body) = body.as_object_mut() {
+            body.insert("trace".to_string(), tag.into());
+        }
+        serde_json::to_writer(&mut *writer, &value)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+}
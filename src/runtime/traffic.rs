@@ -0,0 +1,96 @@
+use crate::{error::Error, protocol::Message};
+use serde::Serialize;
+use std::{
+    env,
+    fs::File,
+    io::{BufWriter, Write},
+    time::Instant,
+};
+
+/// The environment variable holding the path of the traffic log.
+/// Any `{node}` in the path is replaced with the node's ID,
+/// so that nodes sharing an environment write to separate files.
+pub const TRAFFIC_LOG_ENV: &str = "VORTEX_TRAFFIC_LOG";
+
+/// This represents a side channel that receives a pretty-printed, annotated copy
+/// of every message a node reads and writes,
+/// while the compact messages on stdout remain untouched.
+pub struct TrafficLog {
+    /// The log's destination, which is empty when logging is disabled.
+    writer: Option<Box<dyn Write>>,
+    /// The time the log was opened, used to annotate messages with elapsed time.
+    start: Instant,
+}
+
+impl TrafficLog {
+    /// This creates a log that writes to the given writer.
+    pub fn new(writer: impl Write + 'static) -> Self {
+        Self {
+            writer: Some(Box::new(writer)),
+            start: Instant::now(),
+        }
+    }
+
+    /// This creates a log that discards everything.
+    pub fn disabled() -> Self {
+        Self {
+            writer: None,
+            start: Instant::now(),
+        }
+    }
+
+    /// This opens the log at the path in `VORTEX_TRAFFIC_LOG`,
+    /// or returns a disabled log if the variable is unset.
+    pub fn from_env(node_id: &str) -> Result<Self, Error> {
+        match env::var(TRAFFIC_LOG_ENV) {
+            Ok(path) => {
+                let file = File::create(path.replace("{node}", node_id))?;
+                Ok(Self::new(BufWriter::new(file)))
+            }
+            Err(env::VarError::NotPresent) => Ok(Self::disabled()),
+            Err(e) => Err(Error::Env {
+                var: TRAFFIC_LOG_ENV,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    /// This logs a message read by the node.
+    pub fn recv<T>(&mut self, message: &Message<T>) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        self.log("recv", message)
+    }
+
+    /// This logs a message written by the node.
+    pub fn send<T>(&mut self, message: &Message<T>) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        self.log("send", message)
+    }
+
+    fn log<T>(&mut self, direction: &str, message: &Message<T>) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        let value = serde_json::to_value(message)?;
+        let kind = value["body"]["type"].as_str().unwrap_or("unknown");
+        writeln!(
+            writer,
+            "[{:>10.3}s] {} {} {} -> {}",
+            self.start.elapsed().as_secs_f64(),
+            direction,
+            kind,
+            message.src,
+            message.dest,
+        )?;
+        writeln!(writer, "{}", serde_json::to_string_pretty(message)?)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{
+    protocol::{Message, Payload},
+    runtime::{Context, StateMachine},
+};
+use std::convert::Infallible;
+
+#[cfg(feature = "kv")]
+use crate::protocol::kv::Kv;
+
+mod client;
+pub mod kv;
+mod speculation;
+
+pub use client::{KvClient, KvError, KvPayload, LinKv, LwwKv, SeqKv, LIN_KV, LWW_KV, SEQ_KV};
+pub use speculation::{Speculation, SPECULATE_ENV};
+
+/// This is a trait for services that a node exposes to the other nodes in the cluster,
+/// in the style of Maelstrom's own services such as `lin-kv`.
+/// A service answers every request with exactly one reply.
+pub trait Service<T> {
+    /// This handles a request from `src`, returning the payload of the reply,
+    /// which is either a custom payload or an error payload.
+    /// Payloads that are not requests to the service return `None`.
+    fn handle(&mut self, src: &str, request: T) -> Option<Payload<T>>;
+
+    /// This handles a request to a key-value service from `src`,
+    /// which is parsed into `Payload::Kv` instead of a custom payload with the `kv` feature.
+    /// Services that are not key-value services return `None`.
+    #[cfg(feature = "kv")]
+    fn handle_kv(&mut self, _src: &str, _request: Kv) -> Option<Payload<T>> {
+        None
+    }
+}
+
+/// This hosts a service as the node's state machine,
+/// replying to each request with the service's reply.
+pub struct Hosted<S> {
+    service: S,
+}
+
+impl<S> Hosted<S> {
+    /// This hosts the given service.
+    pub fn new(service: S) -> Self {
+        Self { service }
+    }
+
+    /// The hosted service.
+    pub fn service(&self) -> &S {
+        &self.service
+    }
+}
+
+impl<T, S> StateMachine<T> for Hosted<S>
+where
+    S: Service<T>,
+{
+    type Error = Infallible;
+
+    fn apply(
+        &mut self,
+        messages: Vec<Message<T>>,
+        ctx: &mut Context<T>,
+    ) -> Result<(), Self::Error> {
+        for Message { src, dest, body } in messages {
+            let reply = match body.payload {
+                Payload::Custom(request) => self.service.handle(&src, request),
+                #[cfg(feature = "kv")]
+                Payload::Kv(request) => self.service.handle_kv(&src, request),
+                _ => None,
+            };
+            if let Some(payload) = reply {
+                let mut reply = Message::new(&dest, &src, payload);
+                reply.body.in_reply_to = body.msg_id;
+                ctx.push(reply);
+            }
+        }
+        Ok(())
+    }
+}
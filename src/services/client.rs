@@ -0,0 +1,220 @@
+use crate::protocol::{kv::Kv, ErrorCode, FailureKind, Message, Payload};
+use serde_json::Value;
+
+/// The ID of Maelstrom's sequentially consistent key-value service.
+pub const SEQ_KV: &str = "seq-kv";
+
+/// The ID of Maelstrom's linearizable key-value service.
+pub const LIN_KV: &str = "lin-kv";
+
+/// The ID of Maelstrom's last-write-wins key-value service.
+pub const LWW_KV: &str = "lww-kv";
+
+/// The errors returned by Maelstrom's key-value services.
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KvError {
+    #[error("key does not exist")]
+    KeyDoesNotExist,
+    #[error("compare-and-set precondition failed")]
+    PreconditionFailed,
+    #[error("key-value request failed with {0:?}")]
+    Other(ErrorCode),
+    #[error("key-value request was given up on without a reply")]
+    Abandoned,
+}
+
+impl KvError {
+    /// Whether the request may have happened despite the error,
+    /// where requests given up on without a reply may have happened.
+    pub fn failure_kind(self) -> FailureKind {
+        match self {
+            Self::KeyDoesNotExist | Self::PreconditionFailed => FailureKind::Definite,
+            Self::Other(code) => code.failure_kind(),
+            Self::Abandoned => FailureKind::Indefinite,
+        }
+    }
+}
+
+impl From<ErrorCode> for KvError {
+    fn from(code: ErrorCode) -> Self {
+        match code {
+            ErrorCode::KeyDoesNotExist => Self::KeyDoesNotExist,
+            ErrorCode::PreconditionFailed => Self::PreconditionFailed,
+            code => Self::Other(code),
+        }
+    }
+}
+
+/// This is a trait for custom payloads that can hold the messages of Maelstrom's key-value services,
+/// so that a workload can send requests to them alongside its own messages.
+pub trait KvPayload: From<Kv> {
+    /// The key-value message held by the payload, if any.
+    fn kv(&self) -> Option<&Kv>;
+}
+
+impl KvPayload for Kv {
+    fn kv(&self) -> Option<&Kv> {
+        Some(self)
+    }
+}
+
+/// This is a trait for clients of Maelstrom's key-value services,
+/// which only differ in the service their requests are sent to,
+/// so that a state machine generic over the client can swap consistency levels.
+/// The node correlates each reply with its request by msg_id and passes both to
+/// `StateMachine::on_reply`, where `KvClient::reply` returns the result of the request.
+pub trait KvClient {
+    /// The ID of the node sending the requests.
+    fn node_id(&self) -> &str;
+
+    /// The ID of the service the requests are sent to.
+    fn service(&self) -> &str;
+
+    /// This creates a request to the service.
+    fn request<T: KvPayload>(&self, kv: Kv) -> Message<T> {
+        Message::new(self.node_id(), self.service(), T::from(kv))
+    }
+
+    /// This creates a request reading the value of a key.
+    fn read<T: KvPayload>(&self, key: impl Into<Value>) -> Message<T> {
+        self.request(Kv::Read { key: key.into() })
+    }
+
+    /// This creates a request writing the value of a key.
+    fn write<T: KvPayload>(&self, key: impl Into<Value>, value: impl Into<Value>) -> Message<T> {
+        self.request(Kv::Write {
+            key: key.into(),
+            value: value.into(),
+        })
+    }
+
+    /// This creates a request setting a key to `to` if its value is `from`,
+    /// creating it with `to` if it does not exist and `create_if_missing` is set.
+    fn cas<T: KvPayload>(
+        &self,
+        key: impl Into<Value>,
+        from: impl Into<Value>,
+        to: impl Into<Value>,
+        create_if_missing: bool,
+    ) -> Message<T> {
+        self.request(Kv::Cas {
+            key: key.into(),
+            from: from.into(),
+            to: to.into(),
+            create_if_not_exists: create_if_missing,
+        })
+    }
+
+    /// Whether the message is a request to the service, which `StateMachine::expects_reply` should
+    /// return true for so that the node retransmits it and passes its reply to `on_reply`.
+    fn is_request<T: KvPayload>(&self, message: &Message<T>) -> bool {
+        message.dest == self.service()
+            && matches!(&message.body.payload, Payload::Custom(payload) if payload.kv().is_some())
+    }
+
+    /// This returns the result of a request to the service from its reply,
+    /// which is either the service's reply or the error it returned.
+    /// Requests that are not to the service and replies that are not to the request return `None`.
+    fn reply<T: KvPayload>(
+        &self,
+        request: &Message<T>,
+        reply: &Message<T>,
+    ) -> Option<Result<Kv, KvError>> {
+        if !self.is_request(request)
+            || reply.src != self.service()
+            || reply.body.in_reply_to.is_none()
+            || reply.body.in_reply_to != request.body.msg_id
+        {
+            return None;
+        }
+        match &reply.body.payload {
+            Payload::Custom(payload) => payload.kv().cloned().map(Ok),
+            #[cfg(feature = "kv")]
+            Payload::Kv(kv) => Some(Ok(kv.clone())),
+            Payload::Error { code, .. } => Some(Err((*code).into())),
+            _ => None,
+        }
+    }
+
+    /// This returns the result of a request to the service that was given up on without a reply,
+    /// as passed to `StateMachine::on_abandoned`, which is the indefinite `KvError::Abandoned`.
+    /// Requests that are not to the service return `None`.
+    fn abandoned<T: KvPayload>(&self, request: &Message<T>) -> Option<KvError> {
+        self.is_request(request).then_some(KvError::Abandoned)
+    }
+}
+
+/// This represents a client of Maelstrom's `seq-kv` service for a node.
+#[derive(Clone, Debug)]
+pub struct SeqKv {
+    node_id: String,
+}
+
+impl SeqKv {
+    /// This creates a client sending requests from the given node.
+    pub fn new(node_id: &str) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+        }
+    }
+}
+
+impl KvClient for SeqKv {
+    fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn service(&self) -> &str {
+        SEQ_KV
+    }
+}
+
+/// This represents a client of Maelstrom's `lin-kv` service for a node.
+#[derive(Clone, Debug)]
+pub struct LinKv {
+    node_id: String,
+}
+
+impl LinKv {
+    /// This creates a client sending requests from the given node.
+    pub fn new(node_id: &str) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+        }
+    }
+}
+
+impl KvClient for LinKv {
+    fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn service(&self) -> &str {
+        LIN_KV
+    }
+}
+
+/// This represents a client of Maelstrom's `lww-kv` service for a node.
+#[derive(Clone, Debug)]
+pub struct LwwKv {
+    node_id: String,
+}
+
+impl LwwKv {
+    /// This creates a client sending requests from the given node.
+    pub fn new(node_id: &str) -> Self {
+        Self {
+            node_id: node_id.to_string(),
+        }
+    }
+}
+
+impl KvClient for LwwKv {
+    fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    fn service(&self) -> &str {
+        LWW_KV
+    }
+}
@@ -0,0 +1,84 @@
+use crate::{
+    protocol::{ErrorCode, Payload},
+    services::Service,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub use crate::protocol::kv::Kv;
+
+/// This represents a key-value store that a node can host for its peers
+/// with the same wire format as Maelstrom's key-value services.
+#[derive(Debug, Default)]
+pub struct KvStore {
+    /// The values keyed by the JSON encoding of their keys.
+    values: HashMap<String, Value>,
+}
+
+impl KvStore {
+    /// This creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// This returns the value of a key.
+    pub fn get(&self, key: &Value) -> Option<&Value> {
+        self.values.get(&key.to_string())
+    }
+
+    /// This applies a request, returning the payload of its reply with `wrap` wrapping successful replies.
+    /// Replies that are sent to the store return `None`.
+    fn reply<T>(&mut self, request: Kv, wrap: impl FnOnce(Kv) -> Payload<T>) -> Option<Payload<T>> {
+        let reply = match request {
+            Kv::Read { key } => match self.values.get(&key.to_string()) {
+                Some(value) => wrap(Kv::ReadOk {
+                    value: value.clone(),
+                }),
+                None => Payload::error(
+                    ErrorCode::KeyDoesNotExist,
+                    format!("key {} does not exist", key),
+                ),
+            },
+            Kv::Write { key, value } => {
+                self.values.insert(key.to_string(), value);
+                wrap(Kv::WriteOk)
+            }
+            Kv::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.values.get_mut(&key.to_string()) {
+                Some(value) if *value == from => {
+                    *value = to;
+                    wrap(Kv::CasOk)
+                }
+                Some(value) => Payload::error(
+                    ErrorCode::PreconditionFailed,
+                    format!("expected {} but found {}", from, value),
+                ),
+                None if create_if_not_exists => {
+                    self.values.insert(key.to_string(), to);
+                    wrap(Kv::CasOk)
+                }
+                None => Payload::error(
+                    ErrorCode::KeyDoesNotExist,
+                    format!("key {} does not exist", key),
+                ),
+            },
+            Kv::ReadOk { .. } | Kv::WriteOk | Kv::CasOk => return None,
+        };
+        Some(reply)
+    }
+}
+
+impl Service<Kv> for KvStore {
+    fn handle(&mut self, _src: &str, request: Kv) -> Option<Payload<Kv>> {
+        self.reply(request, Payload::Custom)
+    }
+
+    #[cfg(feature = "kv")]
+    fn handle_kv(&mut self, _src: &str, request: Kv) -> Option<Payload<Kv>> {
+        self.reply(request, Payload::Kv)
+    }
+}
@@ -0,0 +1,121 @@
+use serde::Serialize;
+use std::{borrow::Borrow, collections::HashMap, env, hash::Hash};
+
+/// The environment variable that enables speculation when set to anything but `0`.
+pub const SPECULATE_ENV: &str = "VORTEX_SPECULATE";
+
+/// This holds the values of keys in a service as last confirmed by its replies,
+/// along with the values tentatively assumed after requests that failed indefinitely,
+/// so that a workload carries on as if such a request happened instead of first finding out whether it did.
+/// A later reply either confirms a tentative value or contradicts it, in which case it is rolled back.
+/// Tentative values must only be used for requests to the service, which checks them,
+/// and never in replies to clients, which cannot be taken back.
+/// Without speculation enabled, nothing is assumed and only confirmed values are kept.
+#[derive(Debug, Serialize)]
+pub struct Speculation<K, V>
+where
+    K: Eq + Hash,
+{
+    /// The values confirmed by the service.
+    confirmed: HashMap<K, V>,
+    /// The values assumed after requests that failed indefinitely, which have not been confirmed yet.
+    tentative: HashMap<K, V>,
+    /// Whether values are assumed at all.
+    enabled: bool,
+}
+
+impl<K, V> Speculation<K, V>
+where
+    K: Eq + Hash,
+{
+    /// This creates a buffer that assumes the values of indefinite requests.
+    pub fn new() -> Self {
+        Self {
+            confirmed: HashMap::new(),
+            tentative: HashMap::new(),
+            enabled: true,
+        }
+    }
+
+    /// This creates a buffer that only keeps confirmed values.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::new()
+        }
+    }
+
+    /// This creates a buffer that assumes values if `VORTEX_SPECULATE` is set, and a disabled one otherwise.
+    pub fn from_env() -> Self {
+        match env::var(SPECULATE_ENV) {
+            Ok(v) if v != "0" => Self::new(),
+            _ => Self::disabled(),
+        }
+    }
+
+    /// Whether values are assumed at all.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The value of the key, which is the tentative one if any.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.tentative.get(key).or_else(|| self.confirmed.get(key))
+    }
+
+    /// The value of the key as last confirmed by the service.
+    pub fn confirmed<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.confirmed.get(key)
+    }
+
+    /// Whether the value of the key is tentative.
+    pub fn is_tentative<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.tentative.contains_key(key)
+    }
+
+    /// This assumes the value of the key after a request that failed indefinitely,
+    /// returning whether it was assumed, which it is not if speculation is disabled.
+    pub fn speculate(&mut self, key: K, value: V) -> bool {
+        if self.enabled {
+            self.tentative.insert(key, value);
+        }
+        self.enabled
+    }
+
+    /// This records the value of the key from a reply of the service, replacing any tentative value.
+    pub fn confirm(&mut self, key: K, value: V) {
+        self.tentative.remove(&key);
+        self.confirmed.insert(key, value);
+    }
+
+    /// This drops the tentative value of the key after a reply contradicted it, returning it if any.
+    pub fn rollback<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.tentative.remove(key)
+    }
+}
+
+/// The default buffer only keeps confirmed values, as `from_env` does unless `VORTEX_SPECULATE` is set.
+impl<K, V> Default for Speculation<K, V>
+where
+    K: Eq + Hash,
+{
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
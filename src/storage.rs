@@ -0,0 +1,243 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    env, error, fs, io,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+/// The environment variable holding the directory that nodes persist state to,
+/// which defaults to the system's temporary directory.
+pub const STATE_DIR_ENV: &str = "VORTEX_STATE_DIR";
+
+/// The bytes every snapshot starts with.
+const MAGIC: &[u8; 4] = b"VXSN";
+
+/// The version of the snapshot header written by this crate.
+/// Readers reject headers with a newer version instead of misreading them.
+pub const HEADER_VERSION: u16 = 1;
+
+/// The length of the snapshot header, i.e. the magic, the header version and the codec ID.
+const HEADER_LEN: usize = MAGIC.len() + 2 + 1;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SnapshotError {
+    #[error("snapshot io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("snapshot is truncated or missing its header")]
+    Truncated,
+    #[error("snapshot header has an unsupported version {0}")]
+    UnsupportedVersion(u16),
+    #[error("snapshot was written with codec {found} but is being read with codec {expected}")]
+    CodecMismatch { expected: u8, found: u8 },
+    #[error("snapshot codec error: {0}")]
+    Codec(Box<dyn error::Error + Send + Sync>),
+    #[error(
+        "snapshot has schema version {found} which is newer than the current version {current}"
+    )]
+    FutureVersion { found: u32, current: u32 },
+    #[error("no migration registered from schema version {0}")]
+    MissingMigration(u32),
+}
+
+/// This is a trait for the encodings that state snapshots can be written with.
+pub trait SnapshotCodec {
+    /// The ID written to the snapshot header so that a snapshot is only decoded by its codec.
+    const ID: u8;
+
+    /// This encodes the state into bytes.
+    fn encode<S>(&self, state: &S) -> Result<Vec<u8>, SnapshotError>
+    where
+        S: Serialize;
+
+    /// This decodes the state from bytes produced by `encode`.
+    fn decode<S>(&self, bytes: &[u8]) -> Result<S, SnapshotError>
+    where
+        S: DeserializeOwned;
+}
+
+/// This encodes snapshots as JSON, which is slower and larger than a binary encoding
+/// but can be read by hand when debugging.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Json;
+
+impl SnapshotCodec for Json {
+    const ID: u8 = 1;
+
+    fn encode<S>(&self, state: &S) -> Result<Vec<u8>, SnapshotError>
+    where
+        S: Serialize,
+    {
+        serde_json::to_vec(state).map_err(|e| SnapshotError::Codec(e.into()))
+    }
+
+    fn decode<S>(&self, bytes: &[u8]) -> Result<S, SnapshotError>
+    where
+        S: DeserializeOwned,
+    {
+        serde_json::from_slice(bytes).map_err(|e| SnapshotError::Codec(e.into()))
+    }
+}
+
+/// This encodes the state with a versioned header identifying the codec.
+pub fn encode<C, S>(codec: &C, state: &S) -> Result<Vec<u8>, SnapshotError>
+where
+    C: SnapshotCodec,
+    S: Serialize,
+{
+    let body = codec.encode(state)?;
+    let mut bytes = Vec::with_capacity(HEADER_LEN + body.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&HEADER_VERSION.to_be_bytes());
+    bytes.push(C::ID);
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// This decodes state written by `encode`, checking the header before decoding the body.
+pub fn decode<C, S>(codec: &C, bytes: &[u8]) -> Result<S, SnapshotError>
+where
+    C: SnapshotCodec,
+    S: DeserializeOwned,
+{
+    if bytes.len() < HEADER_LEN || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(SnapshotError::Truncated);
+    }
+    let version = u16::from_be_bytes([bytes[MAGIC.len()], bytes[MAGIC.len() + 1]]);
+    if version > HEADER_VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let found = bytes[HEADER_LEN - 1];
+    if found != C::ID {
+        return Err(SnapshotError::CodecMismatch {
+            expected: C::ID,
+            found,
+        });
+    }
+    codec.decode(&bytes[HEADER_LEN..])
+}
+
+/// This writes a snapshot of the state to a file.
+/// The snapshot is written to a temporary file first and then renamed,
+/// so a crash while saving never leaves a partially written snapshot behind.
+pub fn save<C, S>(codec: &C, state: &S, path: impl AsRef<Path>) -> Result<(), SnapshotError>
+where
+    C: SnapshotCodec,
+    S: Serialize,
+{
+    let path = path.as_ref();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, encode(codec, state)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// This reads a snapshot of the state from a file written by `save`.
+pub fn load<C, S>(codec: &C, path: impl AsRef<Path>) -> Result<S, SnapshotError>
+where
+    C: SnapshotCodec,
+    S: DeserializeOwned,
+{
+    decode(codec, &fs::read(path)?)
+}
+
+/// This returns the path that a node persists the state with the given name to.
+/// The path includes the node ID so that nodes sharing a directory do not overwrite each other.
+pub fn state_path(node_id: &str, name: &str) -> PathBuf {
+    env::var_os(STATE_DIR_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+        .join(format!("vortex-{}-{}", node_id, name))
+}
+
+/// This represents state tagged with the version of the schema it was written with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Versioned<T> {
+    /// The schema version of the state.
+    pub version: u32,
+    /// The state itself.
+    pub state: T,
+}
+
+/// A migration upgrading state from one schema version to the next.
+type Migration = Box<dyn Fn(Value) -> Result<Value, SnapshotError>>;
+
+/// This is a registry of migrations used to load state written by older binaries.
+/// Migrations work on the untyped JSON representation of the state,
+/// so the older state types do not have to be kept around.
+pub struct Migrations<T> {
+    /// The schema version of `T`.
+    current: u32,
+    /// The migrations keyed by the version they upgrade from.
+    steps: BTreeMap<u32, Migration>,
+    _state: PhantomData<fn() -> T>,
+}
+
+impl<T> Migrations<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    /// This creates a registry for state whose current schema version is `current`.
+    pub fn new(current: u32) -> Self {
+        Self {
+            current,
+            steps: BTreeMap::new(),
+            _state: PhantomData,
+        }
+    }
+
+    /// This registers a migration upgrading state from version `from` to `from + 1`.
+    pub fn register(
+        mut self,
+        from: u32,
+        migration: impl Fn(Value) -> Result<Value, SnapshotError> + 'static,
+    ) -> Self {
+        self.steps.insert(from, Box::new(migration));
+        self
+    }
+
+    /// This upgrades versioned state to the current schema version by applying
+    /// each migration in turn.
+    pub fn migrate(&self, versioned: Versioned<Value>) -> Result<T, SnapshotError> {
+        let Versioned {
+            mut version,
+            mut state,
+        } = versioned;
+        if version > self.current {
+            return Err(SnapshotError::FutureVersion {
+                found: version,
+                current: self.current,
+            });
+        }
+        while version < self.current {
+            let migration = self
+                .steps
+                .get(&version)
+                .ok_or(SnapshotError::MissingMigration(version))?;
+            state = migration(state)?;
+            version += 1;
+        }
+        serde_json::from_value(state).map_err(|e| SnapshotError::Codec(e.into()))
+    }
+
+    /// This writes state to a file tagged with the current schema version.
+    pub fn save<C>(&self, codec: &C, state: &T, path: impl AsRef<Path>) -> Result<(), SnapshotError>
+    where
+        C: SnapshotCodec,
+    {
+        let versioned = Versioned {
+            version: self.current,
+            state,
+        };
+        save(codec, &versioned, path)
+    }
+
+    /// This reads state from a file written by `save` with any schema version up to the current one.
+    pub fn load<C>(&self, codec: &C, path: impl AsRef<Path>) -> Result<T, SnapshotError>
+    where
+        C: SnapshotCodec,
+    {
+        self.migrate(load(codec, path)?)
+    }
+}
@@ -0,0 +1,113 @@
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+/// The number of children of each node in `Topology::spanning_tree`.
+pub const DEFAULT_FANOUT: usize = 4;
+
+/// This represents an overlay of the cluster, mapping each node to the neighbors it talks to,
+/// which a node can use instead of the topology suggested by Maelstrom
+/// to trade the latency of spreading a message for the number of messages it takes.
+/// Every node must build the same overlay from the same inputs,
+/// so the nodes are ordered by their IDs with `n2` before `n10` and the order they were given in does not matter.
+/// Every strategy connects nodes both ways, so a node's neighbors also have it as a neighbor.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Topology {
+    /// The neighbors of every node.
+    neighbors: BTreeMap<String, Vec<String>>,
+}
+
+impl Topology {
+    /// This creates a custom overlay from the neighbors of every node, e.g. as in a topology message.
+    pub fn new(neighbors: BTreeMap<String, Vec<String>>) -> Self {
+        Self { neighbors }
+    }
+
+    /// This creates an overlay where every node talks to every other node.
+    pub fn fully_connected(node_ids: &[String]) -> Self {
+        let nodes = sorted(node_ids);
+        let mut topology = Self::without_edges(&nodes);
+        for (i, a) in nodes.iter().enumerate() {
+            for b in &nodes[i + 1..] {
+                topology.connect(a, b);
+            }
+        }
+        topology
+    }
+
+    /// This creates an overlay where the nodes form a ring and each node talks to the nodes before and after it.
+    pub fn ring(node_ids: &[String]) -> Self {
+        let nodes = sorted(node_ids);
+        let mut topology = Self::without_edges(&nodes);
+        for (i, node) in nodes.iter().enumerate() {
+            topology.connect(node, &nodes[(i + 1) % nodes.len()]);
+        }
+        topology
+    }
+
+    /// This creates an overlay where the nodes form a tree and every node has `fanout` children,
+    /// so that a message reaches every node in a logarithmic number of hops.
+    pub fn tree(node_ids: &[String], fanout: usize) -> Self {
+        let nodes = sorted(node_ids);
+        let mut topology = Self::without_edges(&nodes);
+        for (i, node) in nodes.iter().enumerate().skip(1) {
+            topology.connect(&nodes[(i - 1) / fanout.max(1)], node);
+        }
+        topology
+    }
+
+    /// This creates a tree overlay where every node has `DEFAULT_FANOUT` children.
+    pub fn spanning_tree(node_ids: &[String]) -> Self {
+        Self::tree(node_ids, DEFAULT_FANOUT)
+    }
+
+    /// This creates a two-level overlay where the nodes are split into groups of `group_size`,
+    /// every node talks to the first node of its group, and the first nodes of the groups talk to each other.
+    pub fn hierarchy(node_ids: &[String], group_size: usize) -> Self {
+        let nodes = sorted(node_ids);
+        let mut topology = Self::without_edges(&nodes);
+        let groups: Vec<&[String]> = nodes.chunks(group_size.max(1)).collect();
+        for (i, group) in groups.iter().enumerate() {
+            for member in &group[1..] {
+                topology.connect(&group[0], member);
+            }
+            for other in &groups[i + 1..] {
+                topology.connect(&group[0], &other[0]);
+            }
+        }
+        topology
+    }
+
+    /// The neighbors of the node, which has none if it is not in the overlay.
+    pub fn neighbors(&self, node_id: &str) -> &[String] {
+        self.neighbors.get(node_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// The neighbors of every node.
+    pub fn as_map(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.neighbors
+    }
+
+    /// This creates an overlay of the nodes where no node talks to any other.
+    fn without_edges(nodes: &[String]) -> Self {
+        Self::new(nodes.iter().map(|n| (n.clone(), Vec::new())).collect())
+    }
+
+    /// This connects two nodes both ways unless they are the same node or already connected.
+    fn connect(&mut self, a: &str, b: &str) {
+        if a == b || self.neighbors(a).iter().any(|n| n == b) {
+            return;
+        }
+        for (from, to) in [(a, b), (b, a)] {
+            if let Some(neighbors) = self.neighbors.get_mut(from) {
+                neighbors.push(to.into());
+            }
+        }
+    }
+}
+
+/// This sorts the node IDs without duplicates, with shorter IDs first so that `n2` comes before `n10`.
+fn sorted(node_ids: &[String]) -> Vec<String> {
+    let mut nodes = node_ids.to_vec();
+    nodes.sort_by(|a, b| (a.len(), a).cmp(&(b.len(), b)));
+    nodes.dedup();
+    nodes
+}
@@ -0,0 +1,130 @@
+//! The derive macros of the `vortex` crate, which are re-exported from it.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Ident};
+
+/// The suffix of the variants that reply to the request variant of the same name.
+const OK_SUFFIX: &str = "Ok";
+
+/// This derives `vortex::protocol::Workload` for an enum of messages,
+/// pairing every `X` variant with the `XOk` variant that replies to it,
+/// and generates a snake case constructor for each `XOk` variant, e.g. `Data::echo_ok(echo)`.
+/// The variants must have named fields or none, and are typed in snake case as with
+/// `#[serde(tag = "type", rename_all = "snake_case")]`.
+#[proc_macro_derive(Workload)]
+pub fn derive_workload(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Workload can only be derived for enums",
+        ));
+    };
+    let names: Vec<String> = data.variants.iter().map(|v| v.ident.to_string()).collect();
+    let mut kinds = Vec::new();
+    let mut reply_kinds = Vec::new();
+    let mut replies = Vec::new();
+    let mut constructors = Vec::new();
+    for variant in &data.variants {
+        let ident = &variant.ident;
+        let name = ident.to_string();
+        let pattern = match &variant.fields {
+            Fields::Named(_) => quote!(Self::#ident { .. }),
+            Fields::Unit => quote!(Self::#ident),
+            Fields::Unnamed(_) => {
+                return Err(Error::new_spanned(
+                    variant,
+                    "Workload variants must have named fields or none",
+                ))
+            }
+        };
+        let kind = snake_case(&name);
+        kinds.push(quote!(#pattern => #kind,));
+        let reply = format!("{}{}", name, OK_SUFFIX);
+        if names.contains(&reply) {
+            let reply_kind = snake_case(&reply);
+            reply_kinds.push(quote!(#pattern => Some(#reply_kind),));
+        }
+        let is_reply = name
+            .strip_suffix(OK_SUFFIX)
+            .is_some_and(|request| names.iter().any(|n| n == request));
+        if !is_reply {
+            continue;
+        }
+        replies.push(pattern);
+        let constructor = Ident::new(&kind, Span::call_site());
+        constructors.push(match &variant.fields {
+            Fields::Named(fields) => {
+                let idents: Vec<_> = fields.named.iter().map(|f| &f.ident).collect();
+                let types: Vec<_> = fields.named.iter().map(|f| &f.ty).collect();
+                quote! {
+                    pub fn #constructor(#(#idents: #types),*) -> Self {
+                        Self::#ident { #(#idents),* }
+                    }
+                }
+            }
+            _ => quote! {
+                pub fn #constructor() -> Self {
+                    Self::#ident
+                }
+            },
+        });
+    }
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let is_reply = match replies.as_slice() {
+        [] => quote!(false),
+        replies => quote!(matches!(self, #(#replies)|*)),
+    };
+    let constructors_impl = (!constructors.is_empty()).then(|| {
+        quote! {
+            #[allow(dead_code)]
+            impl #impl_generics #ident #ty_generics #where_clause {
+                #(#constructors)*
+            }
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics ::vortex::protocol::Workload for #ident #ty_generics #where_clause {
+            fn kind(&self) -> &'static str {
+                match self {
+                    #(#kinds)*
+                }
+            }
+
+            fn reply_kind(&self) -> Option<&'static str> {
+                match self {
+                    #(#reply_kinds)*
+                    #[allow(unreachable_patterns)]
+                    _ => None,
+                }
+            }
+
+            fn is_reply(&self) -> bool {
+                #is_reply
+            }
+        }
+
+        #constructors_impl
+    })
+}
+
+/// This converts a variant name such as `EchoOk` into its snake case, e.g. `echo_ok`.
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}